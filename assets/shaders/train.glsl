@@ -0,0 +1,44 @@
+varying vec4 v_color;
+// Position relative to the start of the piece, along and across it
+varying vec2 v_local;
+varying float v_length;
+varying float v_radius;
+
+#ifdef VERTEX_SHADER
+attribute vec2 a_pos;
+
+attribute vec2 i_start;
+attribute vec2 i_end;
+attribute float i_width;
+attribute vec4 i_color;
+
+uniform mat3 u_projection_matrix;
+uniform mat3 u_view_matrix;
+
+void main() {
+    vec2 delta = i_end - i_start;
+    float len = length(delta);
+    vec2 dir = len > 0.0 ? delta / len : vec2(1.0, 0.0);
+    vec2 normal = vec2(-dir.y, dir.x);
+    float radius = i_width / 2.0;
+    // The quad reaches past both ends to fit the round caps
+    vec2 local = vec2(-radius + a_pos.x * (len + 2.0 * radius), a_pos.y * radius);
+    v_color = i_color;
+    v_local = local;
+    v_length = len;
+    v_radius = radius;
+    vec2 world_pos = i_start + dir * local.x + normal * local.y;
+    vec3 pos = u_projection_matrix * u_view_matrix * vec3(world_pos, 1.0);
+    gl_Position = vec4(pos.xy, 0.0, pos.z);
+}
+#endif
+
+#ifdef FRAGMENT_SHADER
+void main() {
+    vec2 closest = vec2(clamp(v_local.x, 0.0, v_length), 0.0);
+    if (length(v_local - closest) > v_radius) {
+        discard;
+    }
+    gl_FragColor = v_color;
+}
+#endif
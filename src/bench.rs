@@ -0,0 +1,238 @@
+//! Headless benchmarks, run with `cargo run --release -- --bench`. Fails if a nearest node
+//! or segment query among ~100k nodes ever takes longer than [MAX_NEAREST_QUERY].
+
+use super::*;
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const GRID_SIZE: usize = 317; // ~100k nodes
+const GRID_SPACING: f32 = 10.0;
+const QUERIES: usize = 10_000;
+const MAX_NEAREST_QUERY: Duration = Duration::from_millis(1);
+/// Runs of every query in the limit check, the fastest one counting
+const LIMIT_RUNS: usize = 5;
+const ROUTING_GRID_SIZE: usize = 100;
+const ROUTING_TARGETS: usize = 20;
+const ROUTING_QUERIES: usize = 1_000;
+const RENDER_GRID_SIZE: usize = 100;
+const RENDER_TRAINS: usize = 10_000;
+const RENDER_FRAMES: usize = 100;
+
+pub fn run() {
+    spatial_index();
+    routing();
+    rendering();
+}
+
+fn measure(name: &str, queries: usize, mut query: impl FnMut(usize)) {
+    let start = Instant::now();
+    let mut worst = Duration::ZERO;
+    for i in 0..queries {
+        let query_start = Instant::now();
+        query(i);
+        worst = worst.max(query_start.elapsed());
+    }
+    let average = start.elapsed() / queries as u32;
+    println!(
+        "{name}: {:.1}us average, {:.1}us worst over {queries} queries",
+        average.as_secs_f64() * 1e6,
+        worst.as_secs_f64() * 1e6,
+    );
+}
+
+/// Fail if any of `queries` calls of `query` takes longer than `limit`. Every query is
+/// timed [LIMIT_RUNS] times, keeping the fastest, so that being interrupted by the
+/// scheduler doesn't count.
+fn check_limit(name: &str, queries: usize, limit: Duration, mut query: impl FnMut(usize)) {
+    let worst = (0..queries)
+        .map(|i| {
+            (0..LIMIT_RUNS)
+                .map(|_| {
+                    let start = Instant::now();
+                    query(i);
+                    start.elapsed()
+                })
+                .min()
+                .unwrap()
+        })
+        .max()
+        .unwrap_or_default();
+    assert!(
+        worst <= limit,
+        "{name} took {:.1}us at worst, over the {:.1}us limit",
+        worst.as_secs_f64() * 1e6,
+        limit.as_secs_f64() * 1e6,
+    );
+}
+
+/// Jittered grid of nodes, each connected to its right and upper neighbor
+fn grid_network(size: usize) -> Tracks {
+    let mut id_gen = IdGen::new();
+    let mut tracks = Tracks::new(GRID_SPACING);
+    let mut rng = thread_rng();
+    let mut ids = Vec::with_capacity(size * size);
+    for x in 0..size {
+        for y in 0..size {
+            let jitter = vec2(rng.gen_range(-0.3..0.3), rng.gen_range(-0.3..0.3));
+            let pos = (vec2(x as f32, y as f32) + jitter) * GRID_SPACING;
+            ids.push(tracks.add_node(TrackNode::new(&mut id_gen, pos)));
+        }
+    }
+    for x in 0..size {
+        for y in 0..size {
+            if x + 1 < size {
+                tracks.add_connection(ids[x * size + y], ids[(x + 1) * size + y]);
+            }
+            if y + 1 < size {
+                tracks.add_connection(ids[x * size + y], ids[x * size + y + 1]);
+            }
+        }
+    }
+    tracks
+}
+
+fn spatial_index() {
+    let start = Instant::now();
+    let mut tracks = grid_network(GRID_SIZE);
+    println!(
+        "built {} nodes in {:.1}ms",
+        tracks.nodes.len(),
+        start.elapsed().as_secs_f64() * 1e3,
+    );
+
+    let extent = GRID_SIZE as f32 * GRID_SPACING;
+    let mut rng = thread_rng();
+    let points: Vec<vec2<f32>> = (0..QUERIES)
+        .map(|_| vec2(rng.gen_range(0.0..extent), rng.gen_range(0.0..extent)))
+        .collect();
+
+    let nearest_node = |i: usize| {
+        black_box(tracks.nearest_node(points[i], GRID_SPACING));
+    };
+    let nearest_segment = |i: usize| {
+        black_box(tracks.nearest_segment(points[i], GRID_SPACING));
+    };
+    measure("nearest node", QUERIES, nearest_node);
+    measure("nearest segment", QUERIES, nearest_segment);
+    check_limit("nearest node", QUERIES, MAX_NEAREST_QUERY, nearest_node);
+    check_limit(
+        "nearest segment",
+        QUERIES,
+        MAX_NEAREST_QUERY,
+        nearest_segment,
+    );
+    measure("nodes in rect", QUERIES, |i| {
+        let rect = Aabb2::point(points[i]).extend_uniform(5.0 * GRID_SPACING);
+        black_box(tracks.nodes_in_rect(rect));
+    });
+    measure("segments in rect", QUERIES, |i| {
+        let rect = Aabb2::point(points[i]).extend_uniform(5.0 * GRID_SPACING);
+        black_box(tracks.segments_in_rect(rect));
+    });
+
+    let ids: Vec<Id> = tracks.nodes.iter().map(|node| node.id).collect();
+    measure("remove and reinsert node", QUERIES, |i| {
+        let id = ids[i * ids.len() / QUERIES];
+        let connections = tracks.nodes.get(&id).unwrap().connections.clone();
+        let node = tracks.remove_node(id).unwrap();
+        tracks.add_node(node);
+        for other in connections {
+            tracks.add_connection(id, other);
+        }
+    });
+}
+
+/// Uncached A* over the track graph, kept as the baseline for [routing]
+fn astar_pathfind(tracks: &Tracks, from: Id, to: Id) -> Option<Vec<Id>> {
+    let to = tracks.nodes.get(&to).unwrap();
+    let (path, _cost) = pathfinding::directed::astar::astar(
+        &from,
+        |&v| {
+            let v = tracks.nodes.get(&v).unwrap();
+            v.connections
+                .iter()
+                .map(|&u| (u, r32(tracks.segment_length(v.id, u))))
+        },
+        |id| r32((tracks.nodes.get(id).unwrap().pos - to.pos).len()),
+        |&v| v == to.id,
+    )?;
+    Some(path)
+}
+
+fn routing() {
+    let tracks = grid_network(ROUTING_GRID_SIZE);
+    let ids: Vec<Id> = tracks.nodes.iter().map(|node| node.id).collect();
+    let mut rng = thread_rng();
+    let targets: Vec<Id> = ids
+        .choose_multiple(&mut rng, ROUTING_TARGETS)
+        .copied()
+        .collect();
+    let queries: Vec<(Id, Id)> = (0..ROUTING_QUERIES)
+        .map(|_| {
+            (
+                *ids.choose(&mut rng).unwrap(),
+                *targets.choose(&mut rng).unwrap(),
+            )
+        })
+        .collect();
+
+    measure("A* route", ROUTING_QUERIES, |i| {
+        let (from, to) = queries[i];
+        black_box(astar_pathfind(&tracks, from, to));
+    });
+    measure("cached route (cold)", ROUTING_TARGETS, |i| {
+        black_box(tracks.pathfind(ids[0], targets[i]));
+    });
+    measure("cached route (warm)", ROUTING_QUERIES, |i| {
+        let (from, to) = queries[i];
+        black_box(tracks.pathfind(from, to));
+    });
+}
+
+fn rendering() {
+    let mut world = futures::executor::block_on(World::load(&run_dir().join("assets"), None, None));
+    world.tracks = grid_network(RENDER_GRID_SIZE);
+    let resource = world.id_gen.gen();
+    let mut rng = thread_rng();
+    // Trains half way along a horizontal segment, their tail on the previous one
+    for _ in 0..RENDER_TRAINS {
+        let x = rng.gen_range(1..RENDER_GRID_SIZE - 1);
+        let y = rng.gen_range(0..RENDER_GRID_SIZE);
+        let node = |x: usize| {
+            world
+                .tracks
+                .nearest_node(vec2(x as f32, y as f32) * GRID_SPACING, GRID_SPACING)
+                .unwrap()
+        };
+        let (previous, from, to) = (node(x - 1), node(x), node(x + 1));
+        let id = world.build_train(resource, from, 0, Vec::new());
+        let train = world.trains.get_mut(&id).unwrap();
+        train.head = TrackPoint {
+            from,
+            to,
+            ratio: 0.5,
+        };
+        train.tail_nodes = [from, previous].into();
+    }
+
+    measure("track mesh", RENDER_FRAMES, |_| {
+        black_box(track_mesh(&world.tracks, &world.config.track));
+    });
+    measure("train instances", RENDER_FRAMES, |_| {
+        black_box(train_instances(
+            &world.tracks,
+            &world.trains,
+            &world.config.train,
+            None,
+        ));
+    });
+    measure("train dots", RENDER_FRAMES, |_| {
+        black_box(train_instances(
+            &world.tracks,
+            &world.trains,
+            &world.config.train,
+            Some(1.0),
+        ));
+    });
+}
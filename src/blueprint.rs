@@ -0,0 +1,425 @@
+use super::*;
+
+#[derive(Deserialize)]
+pub struct BlueprintConfig {
+    /// Where blueprints are saved to and loaded from
+    pub directory: PathBuf,
+    /// Pasted nodes this close to an existing node connect to it instead
+    pub snap_distance: f32,
+    /// Degrees turned by one rotation
+    pub rotation_step: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct BlueprintTrack {
+    /// Indices into [Blueprint::nodes]
+    pub from: usize,
+    pub to: usize,
+    pub handles: [vec2<f32>; 2],
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct BlueprintFactory {
+    /// Name of the factory type, so that files keep working when types are added
+    pub ty: String,
+    pub pos: vec2<f32>,
+    /// In degrees
+    pub angle: f32,
+    /// Indices into [Blueprint::nodes] of the factory ios, in order
+    pub ios: Vec<usize>,
+}
+
+/// Layout of tracks, factories and stations, positions relative to an anchor
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct Blueprint {
+    pub nodes: Vec<vec2<f32>>,
+    pub tracks: Vec<BlueprintTrack>,
+    pub factories: Vec<BlueprintFactory>,
+    /// Indices into [Blueprint::nodes] that have a station
+    pub stations: Vec<usize>,
+}
+
+impl Blueprint {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path).map_err(|error| error.to_string())?;
+        let blueprint: Self = serde_json::from_str(&json).map_err(|error| error.to_string())?;
+        blueprint.check()?;
+        Ok(blueprint)
+    }
+
+    /// Make sure every index points at a node, as files and replays can hold anything
+    fn check(&self) -> Result<(), String> {
+        let indices = self
+            .tracks
+            .iter()
+            .flat_map(|track| [track.from, track.to])
+            .chain(
+                self.factories
+                    .iter()
+                    .flat_map(|factory| factory.ios.iter().copied()),
+            )
+            .chain(self.stations.iter().copied());
+        for index in indices {
+            if index >= self.nodes.len() {
+                return Err(format!(
+                    "Node {index} out of range, the blueprint has {} nodes",
+                    self.nodes.len()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self).unwrap())
+    }
+}
+
+/// Where and how a blueprint is pasted
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct Placement {
+    /// Where the anchor goes
+    pub pos: vec2<f32>,
+    /// Counterclockwise, in degrees
+    pub rotation: f32,
+    /// Flipped upside down before rotating
+    pub mirror: bool,
+}
+
+impl Placement {
+    pub fn vector(&self, v: vec2<f32>) -> vec2<f32> {
+        let v = if self.mirror { vec2(v.x, -v.y) } else { v };
+        v.rotate(Angle::from_degrees(self.rotation))
+    }
+
+    pub fn point(&self, pos: vec2<f32>) -> vec2<f32> {
+        self.pos + self.vector(pos)
+    }
+
+    /// Direction given in degrees, once placed
+    pub fn angle(&self, degrees: f32) -> Angle<f32> {
+        self.vector(vec2(1.0, 0.0).rotate(Angle::from_degrees(degrees)))
+            .arg()
+    }
+}
+
+/// What pasting a blueprint amounts to, worked out before anything gets built
+pub(crate) struct PastePlan {
+    /// Where every blueprint node ends up
+    pub positions: Vec<vec2<f32>>,
+    /// Existing node every blueprint node connects to instead of getting a new one
+    pub existing: Vec<Option<Id>>,
+    /// Index into [FactoryTypes] of every blueprint factory
+    pub factory_types: Vec<usize>,
+    /// Plan of every blueprint track, none for tracks that are already there
+    pub tracks: Vec<Option<TrackPlan>>,
+    pub price: f32,
+}
+
+impl World {
+    /// Blueprint of the selection, positions relative to `anchor`
+    pub fn capture(&self, selection: &Selection, anchor: vec2<f32>) -> Blueprint {
+        let nodes: Vec<Id> = self.carried_nodes(selection).into_iter().collect();
+        let index: HashMap<Id, usize> = nodes.iter().enumerate().map(|(i, &id)| (id, i)).collect();
+        let tracks = self
+            .tracks
+            .curves
+            .iter()
+            .filter(|((a, b), _)| index.contains_key(a) && index.contains_key(b))
+            .sorted_by_key(|&(&key, _)| key)
+            .map(|(&(a, b), curve)| BlueprintTrack {
+                from: index[&a],
+                to: index[&b],
+                handles: curve.handles,
+            })
+            .collect();
+        let factories = selection
+            .factories
+            .iter()
+            .filter_map(|id| self.factories.get(id))
+            .map(|factory| BlueprintFactory {
+                ty: self.factory_types[factory.ty].name.clone(),
+                pos: factory.pos - anchor,
+                angle: factory_angle(factory).as_degrees(),
+                ios: factory.io.iter().map(|io| index[&io.node]).collect(),
+            })
+            .collect();
+        let stations = self
+            .stations
+            .iter()
+            .filter_map(|station| index.get(&station.node).copied())
+            .sorted()
+            .collect();
+        Blueprint {
+            nodes: nodes
+                .iter()
+                .map(|id| self.tracks.nodes.get(id).unwrap().pos - anchor)
+                .collect(),
+            tracks,
+            factories,
+            stations,
+        }
+    }
+
+    /// Check that `blueprint` can be pasted and work out what it takes.
+    /// Mirrored factories with more than two ios get them in the original order going around,
+    /// so that tracks still lead to the same resources.
+    pub fn plan_paste(
+        &self,
+        blueprint: &Blueprint,
+        placement: Placement,
+    ) -> Result<PastePlan, String> {
+        blueprint.check()?;
+        let mut positions: Vec<vec2<f32>> = blueprint
+            .nodes
+            .iter()
+            .map(|&pos| placement.point(pos))
+            .collect();
+        let mut price = 0.0;
+        let mut factory_types = Vec::new();
+        let mut ios = HashSet::new();
+        for factory in &blueprint.factories {
+            let ty = self
+                .factory_types
+                .factory
+                .iter()
+                .position(|ty| ty.name == factory.ty)
+                .ok_or_else(|| format!("Unknown factory type {:?}", factory.ty))?;
+            let io_count = self.factory_types[ty].io.len();
+            if factory.ios.len() != io_count {
+                return Err(format!(
+                    "{} has {io_count} ios, not {}",
+                    factory.ty,
+                    factory.ios.len()
+                ));
+            }
+            let pos = placement.point(factory.pos);
+            self.check_factory_site(pos, ty)?;
+            price += self.factory_types[ty].price;
+            factory_types.push(ty);
+            let io_positions = self.io_positions(pos, placement.angle(factory.angle), ty);
+            for (&index, io_pos) in factory.ios.iter().zip(io_positions) {
+                positions[index] = io_pos;
+                ios.insert(index);
+            }
+        }
+        // Ios come with their factory, everything else may join the existing tracks
+        let snap_distance = self.config.blueprint.snap_distance;
+        let existing: Vec<Option<Id>> = positions
+            .iter()
+            .enumerate()
+            .map(|(index, &pos)| {
+                if ios.contains(&index) {
+                    return None;
+                }
+                self.tracks.nearest_node(pos, snap_distance)
+            })
+            .collect();
+        let tracks = blueprint
+            .tracks
+            .iter()
+            .map(|track| {
+                if let (Some(a), Some(b)) = (existing[track.from], existing[track.to]) {
+                    if a == b || self.tracks.nodes.get(&a).unwrap().connections.contains(&b) {
+                        return Ok(None);
+                    }
+                }
+                let handles = track.handles.map(|handle| placement.vector(handle));
+                let plan = self.plan_track(positions[track.from], positions[track.to], handles)?;
+                price += plan.price;
+                Ok(Some(plan))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        for &index in &blueprint.stations {
+            let taken = existing[index]
+                .is_some_and(|node| self.stations.iter().any(|station| station.node == node));
+            if !taken {
+                price += self.config.economy.station_price;
+            }
+        }
+        Ok(PastePlan {
+            positions,
+            existing,
+            factory_types,
+            tracks,
+            price,
+        })
+    }
+
+    /// Pay for and build a blueprint
+    pub fn paste(&mut self, blueprint: &Blueprint, placement: Placement) -> Result<(), String> {
+        let plan = self.plan_paste(blueprint, placement)?;
+        self.pay(plan.price)?;
+        let mut nodes = plan.existing;
+        for (factory, &ty) in blueprint.factories.iter().zip(&plan.factory_types) {
+            let pos = placement.point(factory.pos);
+            let id = self.build_factory(pos, placement.angle(factory.angle), ty);
+            let built = self.factories.get(&id).unwrap();
+            for (&index, io) in factory.ios.iter().zip(&built.io) {
+                nodes[index] = Some(io.node);
+            }
+        }
+        let nodes: Vec<Id> = nodes
+            .into_iter()
+            .zip(plan.positions)
+            .map(|(node, pos)| node.unwrap_or_else(|| self.add_node(pos)))
+            .collect();
+        for (track, plan) in blueprint.tracks.iter().zip(plan.tracks) {
+            if let Some(plan) = plan {
+                let (a, b) = (nodes[track.from], nodes[track.to]);
+                let handles = track.handles.map(|handle| placement.vector(handle));
+                self.tracks.add_curve(a, b, handles);
+                self.tracks.set_kind(a, b, plan.kind);
+            }
+        }
+        for &index in &blueprint.stations {
+            let node = nodes[index];
+            if !self.stations.iter().any(|station| station.node == node) {
+                let name = self.next_station_name();
+                self.build_station(node, name);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Game {
+    /// Take a blueprint of the selection in hand, anchored at its center
+    pub(crate) fn capture_blueprint(&mut self) {
+        let Some(bounds) = self.selection_bounds() else {
+            self.show_message("Select something first".to_owned());
+            return;
+        };
+        let anchor = self
+            .snapping
+            .apply(&self.config.drawing.snapping, bounds.center(), None);
+        let blueprint = self.world.capture(&self.selection, anchor);
+        self.selection = Selection::default();
+        self.hold_blueprint(blueprint);
+    }
+
+    pub(crate) fn hold_blueprint(&mut self, blueprint: Blueprint) {
+        let placement = Placement {
+            pos: self.blueprint_pos(),
+            ..default()
+        };
+        self.blueprint = Some((blueprint, placement));
+    }
+
+    /// Snapped cursor position, where the blueprint in hand goes
+    pub(crate) fn blueprint_pos(&self) -> vec2<f32> {
+        self.snapping.apply(
+            &self.config.drawing.snapping,
+            self.cursor_world_position,
+            None,
+        )
+    }
+
+    pub(crate) fn paste_blueprint(&mut self) {
+        if let Some((blueprint, placement)) = self.blueprint.clone() {
+            self.command(Command::Paste {
+                blueprint,
+                placement,
+            });
+        }
+    }
+
+    /// Save the blueprint in hand under the first free name
+    pub(crate) fn save_blueprint(&mut self) {
+        let Some((blueprint, _)) = &self.blueprint else {
+            return;
+        };
+        let directory = &self.config.blueprint.directory;
+        let path = (1..)
+            .map(|n| directory.join(format!("blueprint_{n}.json")))
+            .find(|path| !path.exists())
+            .unwrap();
+        let message = match blueprint.save(&path) {
+            Ok(()) => format!("Blueprint saved to {}", path.display()),
+            Err(error) => format!("Failed to save the blueprint: {error}"),
+        };
+        self.show_message(message);
+    }
+
+    /// Take the next saved blueprint in hand, in file name order
+    pub(crate) fn load_next_blueprint(&mut self) {
+        let paths: Vec<PathBuf> = std::fs::read_dir(&self.config.blueprint.directory)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "json")
+            })
+            .sorted()
+            .collect();
+        if paths.is_empty() {
+            self.show_message("No saved blueprints".to_owned());
+            return;
+        }
+        let path = &paths[self.blueprint_file % paths.len()];
+        self.blueprint_file = self.blueprint_file % paths.len() + 1;
+        match Blueprint::load(path) {
+            Ok(blueprint) => {
+                self.show_message(format!("Blueprint {}", path.display()));
+                self.hold_blueprint(blueprint);
+            }
+            Err(error) => {
+                self.show_message(format!("Failed to load {}: {error}", path.display()));
+            }
+        }
+    }
+
+    pub(crate) fn draw_blueprint(&self, framebuffer: &mut ugli::Framebuffer) {
+        let Some((blueprint, placement)) = &self.blueprint else {
+            return;
+        };
+        let config = &self.config.drawing;
+        let (color, plan) = match self.world.plan_paste(blueprint, *placement) {
+            Ok(plan) => (config.preview_color, Some(plan)),
+            Err(_) => (config.invalid_color, None),
+        };
+        let pos = |index: usize| match &plan {
+            Some(plan) => plan.positions[index],
+            None => placement.point(blueprint.nodes[index]),
+        };
+        for track in &blueprint.tracks {
+            let handles = track.handles.map(|handle| placement.vector(handle));
+            let curve = Curve::new(pos(track.from), pos(track.to), handles);
+            self.geng.draw2d().draw2d(
+                framebuffer,
+                &self.camera,
+                &draw2d::Chain::new(curve.points().to_vec(), self.config.track.width, color, 4),
+            );
+        }
+        for factory in &blueprint.factories {
+            let radius = self
+                .factory_types
+                .factory
+                .iter()
+                .find(|ty| ty.name == factory.ty)
+                .map_or(1.0, |ty| ty.radius);
+            self.geng.draw2d().draw2d(
+                framebuffer,
+                &self.camera,
+                &draw2d::Ellipse::circle(placement.point(factory.pos), radius, color),
+            );
+        }
+        // Mark where the paste joins the existing tracks
+        for node in plan.iter().flat_map(|plan| plan.existing.iter().flatten()) {
+            self.geng.draw2d().draw2d(
+                framebuffer,
+                &self.camera,
+                &draw2d::Ellipse::circle(
+                    self.world.tracks.nodes.get(node).unwrap().pos,
+                    self.config.track.width,
+                    config.selection_color,
+                ),
+            );
+        }
+    }
+}
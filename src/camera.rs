@@ -0,0 +1,131 @@
+use super::*;
+
+/// Animated camera move, eased in and out
+pub struct Transition {
+    pub from: vec2<f32>,
+    pub to: vec2<f32>,
+    /// Seconds since the start
+    pub time: f32,
+}
+
+/// Where the camera goes on its own, as opposed to being dragged around
+#[derive(Default)]
+pub struct CameraControl {
+    /// Train the camera stays centered on
+    pub follow: Option<Id>,
+    pub transition: Option<Transition>,
+}
+
+impl Game {
+    /// Smoothly move the camera to `pos`, dropping a followed train
+    pub(crate) fn jump_to(&mut self, pos: vec2<f32>) {
+        self.camera_control = CameraControl {
+            follow: None,
+            transition: Some(Transition {
+                from: self.camera.center,
+                to: pos,
+                time: 0.0,
+            }),
+        };
+    }
+
+    /// Smoothly move to a train and keep it in the center
+    pub(crate) fn follow(&mut self, train: Id) {
+        self.camera_control = CameraControl {
+            follow: Some(train),
+            transition: Some(Transition {
+                from: self.camera.center,
+                to: self.camera.center,
+                time: 0.0,
+            }),
+        };
+    }
+
+    /// Give the camera back to the player
+    pub(crate) fn release_camera(&mut self) {
+        self.camera_control = CameraControl::default();
+    }
+
+    /// Next factory after the one closest to the view center, in id order
+    pub(crate) fn jump_to_next_factory(&mut self) {
+        let factories: Vec<(Id, vec2<f32>)> = self
+            .world
+            .factories
+            .iter()
+            .sorted_by_key(|factory| factory.id)
+            .map(|factory| (factory.id, factory.pos))
+            .collect();
+        let snap_distance = self.ui_to_world_distance(self.config.control.snap_distance);
+        let current = factories
+            .iter()
+            .position(|&(_, pos)| (pos - self.camera.center).len() < snap_distance);
+        let next = current.map_or(0, |index| index + 1);
+        if let Some(&(_, pos)) = factories.get(next).or(factories.first()) {
+            self.jump_to(pos);
+        }
+    }
+
+    /// Follow the train after the followed one, in id order
+    pub(crate) fn follow_next_train(&mut self) {
+        let trains: Vec<Id> = self
+            .world
+            .trains
+            .iter()
+            .map(|train| train.id)
+            .sorted()
+            .collect();
+        let next = self
+            .camera_control
+            .follow
+            .and_then(|id| trains.iter().position(|&train| train == id))
+            .map_or(0, |index| index + 1);
+        if let Some(&train) = trains.get(next).or(trains.first()) {
+            self.follow(train);
+        }
+    }
+
+    pub(crate) fn update_camera(&mut self, delta_time: f32) {
+        let config = &self.config.control;
+        let window = self.geng.window();
+        let mut direction = vec2::ZERO;
+        for (action, step) in [
+            (Action::PanUp, vec2(0.0, 1.0)),
+            (Action::PanDown, vec2(0.0, -1.0)),
+            (Action::PanLeft, vec2(-1.0, 0.0)),
+            (Action::PanRight, vec2(1.0, 0.0)),
+        ] {
+            if self.config.controls.held(action, window) {
+                direction += step;
+            }
+        }
+        if direction != vec2::ZERO {
+            // Speed is in screens per second, so it feels the same at any zoom
+            let speed = config.pan_speed * *self.camera.fov.value();
+            self.camera.center += direction.normalize_or_zero() * speed * delta_time;
+            self.release_camera();
+            return;
+        }
+
+        let duration = config.transition_duration;
+        if let Some(id) = self.camera_control.follow {
+            let Some(train) = self.world.trains.get(&id) else {
+                self.release_camera();
+                return;
+            };
+            let pos = self.world.tracks.point_pos(train.head);
+            match &mut self.camera_control.transition {
+                Some(transition) => transition.to = pos,
+                None => self.camera.center = pos,
+            }
+        }
+        if let Some(transition) = &mut self.camera_control.transition {
+            transition.time += delta_time;
+            let t = (transition.time / duration).min(1.0);
+            let eased = t * t * (3.0 - 2.0 * t);
+            self.camera.center = transition.from + (transition.to - transition.from) * eased;
+            if t >= 1.0 {
+                self.camera_control.transition = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use super::*;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SegmentKind {
+    Track,
+    /// Crosses water
+    Bridge,
+    /// Goes through mountains
+    Tunnel,
+}
+
+/// Track that can be built along a path
+pub struct TrackPlan {
+    pub kind: SegmentKind,
+    pub price: f32,
+}
+
+/// Check whether a track can follow `points` over the map and what it would cost.
+///
+/// Both ends have to be on buildable terrain. A track crossing water becomes a bridge
+/// and one crossing mountains a tunnel, but a single segment can't be both.
+pub fn plan_track(
+    map: &Map,
+    config: &TrackConfig,
+    points: &[vec2<f32>],
+) -> Result<TrackPlan, String> {
+    for &end in [points.first(), points.last()].iter().flatten() {
+        let terrain = map.terrain_at(*end);
+        if !terrain.is_buildable() {
+            return Err(format!("Can't build on {}", terrain.name()));
+        }
+    }
+    let lengths = map.terrain_along(points);
+    let length = |terrain| lengths.get(&terrain).copied().unwrap_or(0.0);
+    let total: f32 = lengths.values().sum();
+    let kind = match (
+        length(Terrain::Water) > 0.0,
+        length(Terrain::Mountain) > 0.0,
+    ) {
+        (false, false) => SegmentKind::Track,
+        (true, false) => SegmentKind::Bridge,
+        (false, true) => SegmentKind::Tunnel,
+        (true, true) => return Err("Can't cross both water and mountains".to_owned()),
+    };
+    let price = match kind {
+        SegmentKind::Track => {
+            (length(Terrain::Plain) + length(Terrain::Forest) * config.forest_cost_multiplier)
+                * config.cost_per_length
+        }
+        SegmentKind::Bridge | SegmentKind::Tunnel => {
+            let special = if kind == SegmentKind::Bridge {
+                &config.bridge
+            } else {
+                &config.tunnel
+            };
+            if total > special.max_length {
+                return Err(format!(
+                    "{kind:?} too long, at most {} allowed",
+                    special.max_length
+                ));
+            }
+            total * special.cost_per_length
+        }
+    };
+    Ok(TrackPlan { kind, price })
+}
@@ -0,0 +1,162 @@
+use super::*;
+
+const RESOLUTION: usize = 16;
+
+/// Cubic Bezier track segment, pre-sampled so that positions can be looked up by
+/// the distance travelled along it
+pub struct Curve {
+    ends: [vec2<f32>; 2],
+    /// Control point offsets from the start and from the end
+    pub handles: [vec2<f32>; 2],
+    points: Vec<vec2<f32>>,
+    /// Distance along the curve from the start to each of `points`
+    distances: Vec<f32>,
+}
+
+impl Curve {
+    pub fn new(start: vec2<f32>, end: vec2<f32>, handles: [vec2<f32>; 2]) -> Self {
+        let control = [start, start + handles[0], end + handles[1], end];
+        let points: Vec<vec2<f32>> = (0..=RESOLUTION)
+            .map(|i| bezier(control, i as f32 / RESOLUTION as f32))
+            .collect();
+        let mut distances = vec![0.0];
+        for (a, b) in points.iter().copied().tuple_windows() {
+            distances.push(distances.last().unwrap() + (b - a).len());
+        }
+        Self {
+            ends: [start, end],
+            handles,
+            points,
+            distances,
+        }
+    }
+
+    pub fn length(&self) -> f32 {
+        *self.distances.last().unwrap()
+    }
+
+    pub fn points(&self) -> &[vec2<f32>] {
+        &self.points
+    }
+
+    pub fn aabb(&self) -> Aabb2<f32> {
+        Aabb2::points_bounding_box(self.points.iter().copied()).unwrap()
+    }
+
+    /// Position after travelling `ratio` of the length from the start
+    pub fn point_at(&self, ratio: f32) -> vec2<f32> {
+        let distance = ratio.clamp(0.0, 1.0) * self.length();
+        let i = self
+            .distances
+            .partition_point(|&d| d < distance)
+            .clamp(1, self.points.len() - 1);
+        let (a, b) = (self.points[i - 1], self.points[i]);
+        let piece = self.distances[i] - self.distances[i - 1];
+        if piece == 0.0 {
+            return a;
+        }
+        a + (b - a) * ((distance - self.distances[i - 1]) / piece)
+    }
+
+    /// Bezier parameter of the point `ratio` of the length from the start
+    fn parameter_at(&self, ratio: f32) -> f32 {
+        let distance = ratio.clamp(0.0, 1.0) * self.length();
+        let i = self
+            .distances
+            .partition_point(|&d| d < distance)
+            .clamp(1, self.points.len() - 1);
+        let piece = self.distances[i] - self.distances[i - 1];
+        let within = if piece == 0.0 {
+            0.0
+        } else {
+            (distance - self.distances[i - 1]) / piece
+        };
+        (i as f32 - 1.0 + within) / RESOLUTION as f32
+    }
+
+    /// Split point at `ratio` of the length and handles of both halves
+    pub fn split(&self, ratio: f32) -> (vec2<f32>, [vec2<f32>; 2], [vec2<f32>; 2]) {
+        let t = self.parameter_at(ratio);
+        let [start, end] = self.ends;
+        let [p0, p1, p2, p3] = [start, start + self.handles[0], end + self.handles[1], end];
+        let lerp = |a: vec2<f32>, b: vec2<f32>| a + (b - a) * t;
+        let (p01, p12, p23) = (lerp(p0, p1), lerp(p1, p2), lerp(p2, p3));
+        let (p012, p123) = (lerp(p01, p12), lerp(p12, p23));
+        let mid = lerp(p012, p123);
+        (mid, [p01 - p0, p012 - mid], [p123 - mid, p23 - p3])
+    }
+
+    /// Polyline following the curve from one ratio to another, in either direction
+    pub fn points_between(&self, from: f32, to: f32) -> Vec<vec2<f32>> {
+        let (low, high) = (from.min(to), from.max(to));
+        let length = self.length();
+        let mut points = vec![self.point_at(low)];
+        points.extend(
+            self.points
+                .iter()
+                .zip(&self.distances)
+                .filter(|&(_, &d)| d > low * length && d < high * length)
+                .map(|(&p, _)| p),
+        );
+        points.push(self.point_at(high));
+        if from > to {
+            points.reverse();
+        }
+        points
+    }
+
+    /// Ratio of the point on the curve closest to `pos`
+    pub fn closest_ratio(&self, pos: vec2<f32>) -> f32 {
+        let length = self.length();
+        if length == 0.0 {
+            return 0.0;
+        }
+        let (distance, _) = (1..self.points.len())
+            .map(|i| {
+                let (a, b) = (self.points[i - 1], self.points[i]);
+                let t = closest_ratio(a, b, pos);
+                let along = self.distances[i - 1] + (self.distances[i] - self.distances[i - 1]) * t;
+                (along, (a + (b - a) * t - pos).len())
+            })
+            .min_by_key(|&(_, d)| r32(d))
+            .unwrap();
+        distance / length
+    }
+
+    pub fn intersects_rect(&self, rect: Aabb2<f32>) -> bool {
+        self.points
+            .iter()
+            .copied()
+            .tuple_windows()
+            .any(|(a, b)| segment_intersects_rect(a, b, rect))
+    }
+}
+
+fn bezier([p0, p1, p2, p3]: [vec2<f32>; 4], t: f32) -> vec2<f32> {
+    let s = 1.0 - t;
+    p0 * (s * s * s) + p1 * (3.0 * s * s * t) + p2 * (3.0 * s * t * t) + p3 * (t * t * t)
+}
+
+/// Handles for a new track from `start` to `end`.
+///
+/// `start_dir` and `end_dir` are the directions the curve must leave each end in
+/// (pointing into the curve). A missing one mirrors the other across the chord,
+/// which makes a circular-like arc, and with neither the track is straight.
+pub fn curve_handles(
+    start: vec2<f32>,
+    start_dir: Option<vec2<f32>>,
+    end: vec2<f32>,
+    end_dir: Option<vec2<f32>>,
+) -> [vec2<f32>; 2] {
+    let chord = end - start;
+    let third = chord.len() / 3.0;
+    let chord_dir = chord.normalize_or_zero();
+    let mirror = |dir: vec2<f32>| dir - chord_dir * (2.0 * vec2::dot(dir, chord_dir));
+    let (start_dir, end_dir) = match (start_dir, end_dir) {
+        (Some(start_dir), Some(end_dir)) => (start_dir, end_dir),
+        (Some(start_dir), None) => (start_dir, mirror(start_dir)),
+        (None, Some(end_dir)) => (mirror(end_dir), end_dir),
+        (None, None) => (chord_dir, -chord_dir),
+    };
+    [start_dir * third, end_dir * third]
+}
@@ -0,0 +1,33 @@
+/// Player funds. Running costs can take the balance below zero, but nothing can be
+/// bought until it recovers.
+pub struct Wallet {
+    balance: f32,
+}
+
+impl Wallet {
+    pub fn new(balance: f32) -> Self {
+        Self { balance }
+    }
+
+    pub fn balance(&self) -> f32 {
+        self.balance
+    }
+
+    /// Pay `price` if there is enough money, returning whether it was paid
+    pub fn spend(&mut self, price: f32) -> bool {
+        if price > self.balance {
+            return false;
+        }
+        self.balance -= price;
+        true
+    }
+
+    /// Pay `amount` regardless of the balance
+    pub fn charge(&mut self, amount: f32) {
+        self.balance -= amount;
+    }
+
+    pub fn earn(&mut self, amount: f32) {
+        self.balance += amount;
+    }
+}
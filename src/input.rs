@@ -0,0 +1,422 @@
+use std::{collections::BTreeMap, fmt};
+
+use serde::de::{value::StrDeserializer, IntoDeserializer};
+
+use super::*;
+
+#[cfg(test)]
+mod tests;
+
+/// Everything the player can do with a key or mouse button. Actions that only make sense for
+/// the mouse, like [Action::Build], are ignored when bound to a key.
+///
+/// A click without a drag does what the button's bindings do, except that bindings which
+/// only ever start a drag, like `Shift+MouseLeft` for [Action::Select], fall back to the
+/// button's bindings without modifiers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Click to place nodes and tracks, drag to lay track or move the selection
+    Build,
+    CancelDrawing,
+    PanCamera,
+    Select,
+    MoveObject,
+    PanUp,
+    PanDown,
+    PanLeft,
+    PanRight,
+    SpawnTrain,
+    #[serde(rename = "build_factory_0")]
+    BuildFactory0,
+    #[serde(rename = "build_factory_1")]
+    BuildFactory1,
+    #[serde(rename = "build_factory_2")]
+    BuildFactory2,
+    #[serde(rename = "build_factory_3")]
+    BuildFactory3,
+    #[serde(rename = "build_factory_4")]
+    BuildFactory4,
+    PlaceStation,
+    AddToSchedule,
+    UpgradeLoader,
+    NextWagon,
+    ToggleGrid,
+    ToggleAngleSnapping,
+    ToggleLengthSnapping,
+    ToggleStatistics,
+    ExportStatistics,
+    NextOverlay,
+    FollowNextTrain,
+    JumpToNextFactory,
+    Delete,
+    CopySelection,
+    /// Drop the selection and the blueprint in hand
+    Clear,
+    CaptureBlueprint,
+    LoadNextBlueprint,
+    SaveBlueprint,
+    RotateBlueprint,
+    MirrorBlueprint,
+    ShowControls,
+}
+
+impl Action {
+    /// Whether the action does nothing unless the mouse is dragged
+    pub fn drag_only(self) -> bool {
+        matches!(
+            self,
+            Action::PanCamera | Action::Select | Action::MoveObject
+        )
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Action::Build => "Build",
+            Action::CancelDrawing => "Cancel drawing",
+            Action::PanCamera => "Drag the view",
+            Action::Select => "Box select",
+            Action::MoveObject => "Move node or factory",
+            Action::PanUp => "Pan up",
+            Action::PanDown => "Pan down",
+            Action::PanLeft => "Pan left",
+            Action::PanRight => "Pan right",
+            Action::SpawnTrain => "Spawn train",
+            Action::BuildFactory0 => "Build factory 0",
+            Action::BuildFactory1 => "Build factory 1",
+            Action::BuildFactory2 => "Build factory 2",
+            Action::BuildFactory3 => "Build factory 3",
+            Action::BuildFactory4 => "Build factory 4",
+            Action::PlaceStation => "Place station",
+            Action::AddToSchedule => "Add station to schedule",
+            Action::UpgradeLoader => "Upgrade loader",
+            Action::NextWagon => "Next wagon",
+            Action::ToggleGrid => "Grid snapping",
+            Action::ToggleAngleSnapping => "Angle snapping",
+            Action::ToggleLengthSnapping => "Length snapping",
+            Action::ToggleStatistics => "Statistics",
+            Action::ExportStatistics => "Export statistics",
+            Action::NextOverlay => "Next overlay",
+            Action::FollowNextTrain => "Follow next train",
+            Action::JumpToNextFactory => "Jump to next factory",
+            Action::Delete => "Delete selection",
+            Action::CopySelection => "Copy selection to cursor",
+            Action::Clear => "Drop selection and blueprint",
+            Action::CaptureBlueprint => "Blueprint from selection",
+            Action::LoadNextBlueprint => "Load next blueprint",
+            Action::SaveBlueprint => "Save blueprint",
+            Action::RotateBlueprint => "Rotate blueprint",
+            Action::MirrorBlueprint => "Mirror blueprint",
+            Action::ShowControls => "Show controls",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+}
+
+impl Modifiers {
+    pub fn held(window: &geng::Window) -> Self {
+        let pressed = |keys: [geng::Key; 2]| keys.into_iter().any(|key| window.is_key_pressed(key));
+        Self {
+            shift: pressed([geng::Key::ShiftLeft, geng::Key::ShiftRight]),
+            ctrl: pressed([geng::Key::ControlLeft, geng::Key::ControlRight]),
+            alt: pressed([geng::Key::AltLeft, geng::Key::AltRight]),
+        }
+    }
+
+    /// Whether everything in `self` is held in `held`
+    fn within(self, held: Self) -> bool {
+        (!self.shift || held.shift) && (!self.ctrl || held.ctrl) && (!self.alt || held.alt)
+    }
+
+    fn count(self) -> usize {
+        [self.shift, self.ctrl, self.alt]
+            .into_iter()
+            .filter(|&held| held)
+            .count()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Input {
+    Key(geng::Key),
+    Mouse(geng::MouseButton),
+}
+
+/// Key or mouse button with the modifiers that have to be held with it, written like
+/// `Ctrl+Shift+S` or `MouseRight`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Binding {
+    pub modifiers: Modifiers,
+    pub input: Input,
+}
+
+/// Parse a unit variant by its name
+fn variant<T: for<'de> Deserialize<'de>>(name: &str) -> Result<T, String> {
+    let deserializer: StrDeserializer<serde::de::value::Error> = name.into_deserializer();
+    T::deserialize(deserializer).map_err(|error| error.to_string())
+}
+
+impl TryFrom<String> for Binding {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        let mut parts: Vec<&str> = text.split('+').collect();
+        let input = parts.pop().unwrap();
+        let mut modifiers = Modifiers::default();
+        for part in parts {
+            match part {
+                "Shift" => modifiers.shift = true,
+                "Ctrl" => modifiers.ctrl = true,
+                "Alt" => modifiers.alt = true,
+                _ => return Err(format!("Unknown modifier {part:?} in {text:?}")),
+            }
+        }
+        let input = match input.strip_prefix("Mouse") {
+            Some(button) => Input::Mouse(variant(button)?),
+            None => Input::Key(variant(input)?),
+        };
+        Ok(Self { modifiers, input })
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (held, name) in [
+            (self.modifiers.ctrl, "Ctrl"),
+            (self.modifiers.alt, "Alt"),
+            (self.modifiers.shift, "Shift"),
+        ] {
+            if held {
+                write!(f, "{name}+")?;
+            }
+        }
+        match self.input {
+            Input::Key(key) => write!(f, "{key:?}"),
+            Input::Mouse(button) => write!(f, "Mouse{button:?}"),
+        }
+    }
+}
+
+/// Bindings of every action, any number per action
+#[derive(Deserialize)]
+#[serde(try_from = "BTreeMap<String, Vec<Binding>>")]
+pub struct ControlsConfig {
+    pub bindings: BTreeMap<Action, Vec<Binding>>,
+}
+
+impl TryFrom<BTreeMap<String, Vec<Binding>>> for ControlsConfig {
+    type Error = String;
+
+    fn try_from(bindings: BTreeMap<String, Vec<Binding>>) -> Result<Self, String> {
+        let bindings = bindings
+            .into_iter()
+            .map(|(action, bindings)| Ok((variant(&action)?, bindings)))
+            .collect::<Result<_, String>>()?;
+        Ok(Self { bindings })
+    }
+}
+
+impl ControlsConfig {
+    /// Actions triggered by `input` while `held` is held. Only the bindings with the most
+    /// modifiers count, so that `Ctrl+C` doesn't also do what `C` does.
+    pub fn actions(&self, input: Input, held: Modifiers) -> Vec<Action> {
+        let matching: Vec<(Action, usize)> = self
+            .bindings
+            .iter()
+            .flat_map(|(&action, bindings)| {
+                bindings
+                    .iter()
+                    .filter(|binding| binding.input == input && binding.modifiers.within(held))
+                    .map(move |binding| (action, binding.modifiers.count()))
+            })
+            .collect();
+        let most = matching.iter().map(|&(_, count)| count).max();
+        matching
+            .into_iter()
+            .filter(|&(_, count)| Some(count) == most)
+            .map(|(action, _)| action)
+            .dedup()
+            .collect()
+    }
+
+    /// Actions of a click with `input`, see [Action] for how drag-only bindings fall back
+    pub fn click_actions(&self, input: Input, held: Modifiers) -> Vec<Action> {
+        let actions = self.actions(input, held);
+        if actions.iter().all(|action| action.drag_only()) {
+            return self.actions(input, Modifiers::default());
+        }
+        actions
+    }
+
+    /// Whether anything bound to `action` is held down right now
+    pub fn held(&self, action: Action, window: &geng::Window) -> bool {
+        let modifiers = Modifiers::held(window);
+        self.bindings.get(&action).is_some_and(|bindings| {
+            bindings.iter().any(|binding| {
+                binding.modifiers.within(modifiers)
+                    && match binding.input {
+                        Input::Key(key) => window.is_key_pressed(key),
+                        Input::Mouse(button) => window.is_button_pressed(button),
+                    }
+            })
+        })
+    }
+
+    /// All bindings of `action`, for showing to the player
+    pub fn describe(&self, action: Action) -> String {
+        match self.bindings.get(&action) {
+            Some(bindings) if !bindings.is_empty() => bindings.iter().join(" / "),
+            _ => "unbound".to_owned(),
+        }
+    }
+}
+
+impl Game {
+    pub(crate) fn actions(&self, input: Input) -> Vec<Action> {
+        let window = self.geng.window();
+        self.config.controls.actions(input, Modifiers::held(window))
+    }
+
+    /// Do what clicking `button` at `hover` without dragging does
+    pub(crate) fn click_button(&mut self, button: geng::MouseButton, hover: Hover) {
+        let held = Modifiers::held(self.geng.window());
+        for action in self
+            .config
+            .controls
+            .click_actions(Input::Mouse(button), held)
+        {
+            match action {
+                Action::Build => self.click(hover),
+                _ => self.perform(action),
+            }
+        }
+    }
+
+    /// Do what a key or click bound to `action` does
+    pub(crate) fn perform(&mut self, action: Action) {
+        match action {
+            Action::SpawnTrain => {
+                self.spawn_train();
+            }
+            Action::BuildFactory0 => {
+                self.spawn_factory(0);
+            }
+            Action::BuildFactory1 => {
+                self.spawn_factory(1);
+            }
+            Action::BuildFactory2 => {
+                self.spawn_factory(2);
+            }
+            Action::BuildFactory3 => {
+                self.spawn_factory(3);
+            }
+            Action::BuildFactory4 => {
+                self.spawn_factory(4);
+            }
+            Action::ToggleGrid => {
+                self.snapping.grid = !self.snapping.grid;
+                self.update_hover();
+            }
+            Action::ToggleAngleSnapping => {
+                self.snapping.angle = !self.snapping.angle;
+                self.update_hover();
+            }
+            Action::ToggleLengthSnapping => {
+                self.snapping.length = !self.snapping.length;
+                self.update_hover();
+            }
+            Action::PlaceStation => {
+                self.place_station();
+            }
+            Action::AddToSchedule => {
+                if let Some(station) = self.hovered_station() {
+                    self.schedule_draft.push(station.name.clone());
+                }
+            }
+            Action::UpgradeLoader => {
+                self.upgrade_loader();
+            }
+            Action::NextWagon => {
+                self.wagon_draft = (self.wagon_draft + 1) % self.config.train.wagon.len();
+            }
+            Action::ToggleStatistics => {
+                self.show_statistics = !self.show_statistics;
+            }
+            Action::ExportStatistics => {
+                self.export_statistics();
+            }
+            Action::NextOverlay => {
+                self.overlay = Overlay::next(self.overlay);
+            }
+            Action::FollowNextTrain => {
+                self.follow_next_train();
+            }
+            Action::JumpToNextFactory => {
+                self.jump_to_next_factory();
+            }
+            Action::Delete => {
+                let selection = mem::take(&mut self.selection);
+                self.command(Command::Delete { selection });
+            }
+            Action::CopySelection => {
+                self.copy_selection_to_cursor();
+            }
+            Action::Clear => {
+                self.selection = Selection::default();
+                self.blueprint = None;
+            }
+            Action::CaptureBlueprint => {
+                self.capture_blueprint();
+            }
+            Action::LoadNextBlueprint => {
+                self.load_next_blueprint();
+            }
+            Action::SaveBlueprint => {
+                self.save_blueprint();
+            }
+            Action::RotateBlueprint => {
+                if let Some((_, placement)) = &mut self.blueprint {
+                    placement.rotation =
+                        (placement.rotation + self.config.blueprint.rotation_step) % 360.0;
+                }
+            }
+            Action::MirrorBlueprint => {
+                if let Some((_, placement)) = &mut self.blueprint {
+                    placement.mirror = !placement.mirror;
+                }
+            }
+            Action::ShowControls => {
+                self.show_controls = !self.show_controls;
+            }
+            Action::CancelDrawing => {
+                self.drawing = None;
+            }
+            // Need to know where the mouse is, see [Game::click_button]
+            Action::Build
+            | Action::PanCamera
+            | Action::Select
+            | Action::MoveObject
+            | Action::PanUp
+            | Action::PanDown
+            | Action::PanLeft
+            | Action::PanRight => {}
+        }
+    }
+
+    /// Every bound action with its bindings
+    pub(crate) fn controls_lines(&self) -> Vec<String> {
+        let controls = &self.config.controls;
+        controls
+            .bindings
+            .keys()
+            .map(|&action| format!("{}: {}", action.describe(), controls.describe(action)))
+            .collect()
+    }
+}
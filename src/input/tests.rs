@@ -0,0 +1,109 @@
+//! Parsing and matching of bindings, no window needed
+
+use super::*;
+
+fn binding(text: &str) -> Binding {
+    Binding::try_from(text.to_owned()).unwrap()
+}
+
+fn controls(bindings: &[(&str, &[&str])]) -> ControlsConfig {
+    let bindings = bindings
+        .iter()
+        .map(|&(action, texts)| {
+            (
+                action.to_owned(),
+                texts.iter().map(|&text| binding(text)).collect(),
+            )
+        })
+        .collect::<BTreeMap<_, _>>();
+    ControlsConfig::try_from(bindings).unwrap()
+}
+
+#[test]
+fn parse() {
+    assert_eq!(
+        binding("Ctrl+Shift+S"),
+        Binding {
+            modifiers: Modifiers {
+                shift: true,
+                ctrl: true,
+                alt: false,
+            },
+            input: Input::Key(geng::Key::S),
+        }
+    );
+    assert_eq!(
+        binding("MouseRight"),
+        Binding {
+            modifiers: Modifiers::default(),
+            input: Input::Mouse(geng::MouseButton::Right),
+        }
+    );
+    for broken in ["Super+S", "Ctrl+", "Shift+Nothing", "MouseSide", ""] {
+        assert!(
+            Binding::try_from(broken.to_owned()).is_err(),
+            "{broken:?} parsed"
+        );
+    }
+    let unknown_action = BTreeMap::from([("fly".to_owned(), vec![binding("F")])]);
+    assert!(ControlsConfig::try_from(unknown_action).is_err());
+}
+
+#[test]
+fn display() {
+    // Modifiers always come out in the same order, whatever order they were written in
+    assert_eq!(
+        binding("Shift+Alt+Ctrl+Digit1").to_string(),
+        "Ctrl+Alt+Shift+Digit1"
+    );
+    for text in ["Delete", "Ctrl+C", "Shift+MouseLeft", "MouseMiddle"] {
+        assert_eq!(binding(text).to_string(), text);
+    }
+}
+
+#[test]
+fn actions() {
+    let controls = controls(&[
+        ("build", &["MouseLeft"]),
+        ("select", &["Shift+MouseLeft"]),
+        ("move_object", &["Ctrl+MouseLeft"]),
+        ("cancel_drawing", &["MouseRight"]),
+        ("pan_camera", &["MouseRight", "MouseMiddle"]),
+        ("copy_selection", &["C"]),
+        ("capture_blueprint", &["Ctrl+C"]),
+    ]);
+    let left = Input::Mouse(geng::MouseButton::Left);
+    let right = Input::Mouse(geng::MouseButton::Right);
+    let c = Input::Key(geng::Key::C);
+    let shift = Modifiers {
+        shift: true,
+        ..default()
+    };
+    let ctrl = Modifiers {
+        ctrl: true,
+        ..default()
+    };
+
+    // The binding with the most held modifiers wins
+    assert_eq!(controls.actions(left, default()), vec![Action::Build]);
+    assert_eq!(controls.actions(left, shift), vec![Action::Select]);
+    assert_eq!(controls.actions(c, ctrl), vec![Action::CaptureBlueprint]);
+    // Extra modifiers don't stop a binding from matching
+    assert_eq!(controls.actions(c, shift), vec![Action::CopySelection]);
+    // Several actions can share a binding
+    assert_eq!(
+        controls.actions(right, default()),
+        vec![Action::CancelDrawing, Action::PanCamera]
+    );
+    assert!(controls
+        .actions(Input::Key(geng::Key::X), default())
+        .is_empty());
+
+    // Clicks without a drag fall back to the plain button when only drags are bound
+    assert_eq!(controls.click_actions(left, shift), vec![Action::Build]);
+    assert_eq!(controls.click_actions(left, ctrl), vec![Action::Build]);
+    assert_eq!(
+        controls.click_actions(right, default()),
+        vec![Action::CancelDrawing, Action::PanCamera]
+    );
+}
@@ -3,9 +3,83 @@ use std::collections::VecDeque;
 use geng::prelude::*;
 use itertools::Itertools;
 
+mod bench;
+mod blueprint;
+mod camera;
+mod construction;
+mod curve;
+mod economy;
+mod input;
+mod map;
+mod minimap;
+mod overlay;
+mod render;
+mod replay;
+mod routing;
+mod scenario;
+mod selection;
+mod snapping;
+mod spatial;
+mod station;
+mod statistics;
+mod world;
+
+use blueprint::*;
+use camera::*;
+use construction::*;
+use curve::*;
+use economy::*;
+use input::*;
+use map::*;
+use minimap::*;
+use overlay::*;
+use render::*;
+use replay::*;
+use routing::*;
+use scenario::*;
+use selection::*;
+use snapping::*;
+use spatial::*;
+use station::*;
+use statistics::*;
+use world::*;
+
+#[derive(Deserialize)]
+struct SnappingConfig {
+    grid: f32,
+    /// In degrees
+    angle: f32,
+    length: f32,
+}
+
 #[derive(Deserialize)]
 struct DrawingConfig {
     preview_color: Rgba<f32>,
+    /// Preview of a track that can't be built
+    invalid_color: Rgba<f32>,
+    selection_color: Rgba<f32>,
+    /// Fill of the box being dragged out
+    selection_box_color: Rgba<f32>,
+    snapping: SnappingConfig,
+    /// Longest piece of a dragged track before a node is placed
+    drag_spacing: f32,
+    /// Shortest piece of a dragged track that gets a node of its own
+    drag_min_spacing: f32,
+    /// Turn in degrees after which a dragged track gets a node regardless of its length
+    drag_max_turn: f32,
+}
+
+#[derive(Deserialize)]
+struct HudConfig {
+    /// In `target_window_height` units
+    text_size: f32,
+    margin: f32,
+    color: Rgba<f32>,
+    /// Seconds a message stays on the screen
+    message_duration: f32,
+    /// Text on the victory or defeat screen
+    title_size: f32,
+    overlay_color: Rgba<f32>,
 }
 
 #[derive(Deserialize)]
@@ -13,14 +87,74 @@ struct ControlConfig {
     target_window_height: f32,
     min_drag_distance: f32,
     zoom_speed: f32,
-    drag_timer: f64,
     snap_distance: f32,
+    /// Keyboard panning, in screens per second
+    pan_speed: f32,
+    /// Seconds the camera takes to jump to a factory or train
+    transition_duration: f32,
+}
+
+#[derive(Deserialize)]
+struct RoutingConfig {
+    /// Per train already on a segment
+    occupied_segment_penalty: f32,
+    /// Per train heading to or standing at a station, both when passing through and when
+    /// choosing it as a target
+    waiting_train_penalty: f32,
+    /// For turning back instead of continuing forward
+    reversal_penalty: f32,
+}
+
+/// Bridges and tunnels
+#[derive(Deserialize)]
+struct SpecialSegmentConfig {
+    max_length: f32,
+    cost_per_length: f32,
+    /// Trains never go faster than this on the segment
+    speed_limit: f32,
+    color: Rgba<f32>,
 }
 
 #[derive(Deserialize)]
 struct TrackConfig {
     width: f32,
     color: Rgba<f32>,
+    index_cell_size: f32,
+    routing: RoutingConfig,
+    cost_per_length: f32,
+    /// Cost of track through forest relative to open ground
+    forest_cost_multiplier: f32,
+    bridge: SpecialSegmentConfig,
+    tunnel: SpecialSegmentConfig,
+}
+
+impl TrackConfig {
+    fn speed_limit(&self, kind: SegmentKind) -> f32 {
+        match kind {
+            SegmentKind::Track => f32::INFINITY,
+            SegmentKind::Bridge => self.bridge.speed_limit,
+            SegmentKind::Tunnel => self.tunnel.speed_limit,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct EconomyConfig {
+    starting_balance: f32,
+    train_price: f32,
+    /// Per train per second
+    train_running_cost: f32,
+    station_price: f32,
+}
+
+#[derive(Deserialize)]
+struct SimulationConfig {
+    /// Seconds simulated per step
+    tick: f32,
+    /// Steps taken in one frame at most, the game slows down rather than catching up
+    max_steps: usize,
+    /// Seconds between saves of a replay being recorded
+    replay_save_interval: f32,
 }
 
 #[derive(Deserialize)]
@@ -30,18 +164,25 @@ struct FovConfig {
     max: f32,
 }
 
+#[derive(Deserialize)]
+struct WagonType {
+    name: String,
+    /// Multiplier for the loading speed of the station
+    load_speed: f32,
+}
+
 #[derive(Deserialize)]
 struct TrainConfig {
     width: f32,
     capacity: f32,
     color: Rgba<f32>,
+    wagon: Vec<WagonType>,
 }
 
 #[derive(Deserialize)]
 struct TestConfig {
     train_length: f32,
     train_speed: f32,
-    train_load_speed: f32,
     text_color: Rgba<f32>,
     text_size: f32,
     amount_size: f32,
@@ -53,6 +194,20 @@ struct FactoryIoConfig {
     r#type: IoType,
     resource: String,
     speed: Option<f32>,
+    /// Initial loader, index into [FactoryTypes::loader]
+    #[serde(default)]
+    loader: usize,
+    /// Paid per unit of the resource delivered to this input
+    sale_price: Option<f32>,
+}
+
+#[derive(Deserialize)]
+struct LoaderType {
+    name: String,
+    /// Cargo moved per second
+    speed: f32,
+    /// For upgrading to this tier
+    price: f32,
 }
 
 #[derive(Deserialize)]
@@ -61,11 +216,27 @@ struct FactoryType {
     radius: f32,
     io: Vec<FactoryIoConfig>,
     color: Rgba<f32>,
+    price: f32,
+    /// Resource of the map deposit the factory has to be built on
+    deposit: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ResourceType {
+    name: String,
+    color: Rgba<f32>,
 }
 
 #[derive(Deserialize)]
 struct FactoryTypes {
     factory: Vec<FactoryType>,
+    /// Loading equipment tiers, from the slowest
+    loader: Vec<LoaderType>,
+    /// Colors of resources, used in graphs and overlays
+    #[serde(default)]
+    resource: Vec<ResourceType>,
+    /// For resources missing from [FactoryTypes::resource]
+    default_resource_color: Rgba<f32>,
 }
 
 impl FactoryTypes {
@@ -88,6 +259,12 @@ struct FactoryConfig {}
 struct StationConfig {
     radius: f32,
     color: Rgba<f32>,
+    platform_length: f32,
+    platform_width: f32,
+    /// How far beyond its radius a factory can feed or draw from a standalone station
+    feed_radius: f32,
+    /// Gap between trains holding in a station queue
+    queue_spacing: f32,
 }
 
 #[derive(Deserialize)]
@@ -97,7 +274,17 @@ struct Config {
     fov: FovConfig,
     track: TrackConfig,
     drawing: DrawingConfig,
+    hud: HudConfig,
+    economy: EconomyConfig,
+    simulation: SimulationConfig,
+    statistics: StatisticsConfig,
+    overlay: OverlayConfig,
+    minimap: MinimapConfig,
+    lod: LodConfig,
+    blueprint: BlueprintConfig,
+    map: MapConfig,
     control: ControlConfig,
+    controls: ControlsConfig,
     test: TestConfig,
     train: TrainConfig,
     factory: FactoryConfig,
@@ -109,13 +296,31 @@ enum Drawing {
     FromNode { id: Id },
 }
 
+impl Drawing {
+    fn track_end(self) -> TrackEnd {
+        match self {
+            Drawing::FromScratch { start } => TrackEnd::New(start),
+            Drawing::FromNode { id } => TrackEnd::Node(id),
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 enum Hover {
     Nothing { pos: vec2<f32> },
     TrackNode { id: Id },
 }
 
-#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Hash)]
+impl Hover {
+    fn track_end(self) -> TrackEnd {
+        match self {
+            Hover::Nothing { pos } => TrackEnd::New(pos),
+            Hover::TrackNode { id } => TrackEnd::Node(id),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 struct Id(u64);
 
 struct IdGen {
@@ -133,7 +338,7 @@ impl IdGen {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 struct TrackPoint {
     from: Id,
     to: Id,
@@ -157,47 +362,285 @@ impl TrackNode {
     }
 }
 
-#[derive(Default)]
+/// Shortest paths from every reachable node towards a single target
+struct RouteTree {
+    /// Next node on the way to the target and the distance to the target
+    next: HashMap<Id, (Id, R32)>,
+}
+
 struct Tracks {
     nodes: Collection<TrackNode>,
+    /// Geometry of every connection, oriented as its [Tracks::segment_key]
+    curves: HashMap<(Id, Id), Curve>,
+    /// Segments other than [SegmentKind::Track], keyed by [Tracks::segment_key]
+    kinds: HashMap<(Id, Id), SegmentKind>,
+    node_index: SpatialGrid<Id>,
+    segment_index: SpatialGrid<(Id, Id)>,
+    /// Cleared on every topology change
+    routes: RefCell<HashMap<Id, Rc<RouteTree>>>,
+    /// Bumped on every change to the drawn geometry
+    revision: u64,
 }
 
 impl Tracks {
+    fn new(index_cell_size: f32) -> Self {
+        Self {
+            nodes: Collection::new(),
+            curves: HashMap::new(),
+            kinds: HashMap::new(),
+            node_index: SpatialGrid::new(index_cell_size),
+            segment_index: SpatialGrid::new(index_cell_size),
+            routes: default(),
+            revision: 0,
+        }
+    }
+
+    fn segment_key(a: Id, b: Id) -> (Id, Id) {
+        (a.min(b), a.max(b))
+    }
+
+    /// Curve between two connected nodes and whether it goes from `to` to `from`
+    fn curve(&self, from: Id, to: Id) -> (&Curve, bool) {
+        let key = Self::segment_key(from, to);
+        (&self.curves[&key], key.0 != from)
+    }
+
+    fn add_node(&mut self, node: TrackNode) -> Id {
+        let id = node.id;
+        self.node_index.insert(id, Aabb2::point(node.pos));
+        self.nodes.insert(node);
+        id
+    }
+
+    fn remove_node(&mut self, id: Id) -> Option<TrackNode> {
+        let connections: Vec<Id> = self.nodes.get(&id)?.connections.iter().copied().collect();
+        for other in connections {
+            self.remove_connection(id, other);
+        }
+        let node = self.nodes.remove(&id)?;
+        self.node_index.remove(id, Aabb2::point(node.pos));
+        Some(node)
+    }
+
     fn add_connection(&mut self, a: Id, b: Id) {
+        let a_pos = self.nodes.get(&a).unwrap().pos;
+        let b_pos = self.nodes.get(&b).unwrap().pos;
+        self.add_curve(a, b, curve_handles(a_pos, None, b_pos, None));
+    }
+
+    /// Connect two nodes with a curve, `handles` being control point offsets from `a` and `b`
+    fn add_curve(&mut self, a: Id, b: Id, handles: [vec2<f32>; 2]) {
+        if a == b || self.nodes.get(&a).unwrap().connections.contains(&b) {
+            return;
+        }
         self.nodes.get_mut(&a).unwrap().connections.insert(b);
         self.nodes.get_mut(&b).unwrap().connections.insert(a);
+        let key = Self::segment_key(a, b);
+        let curve = {
+            let (start, end) = (
+                self.nodes.get(&key.0).unwrap(),
+                self.nodes.get(&key.1).unwrap(),
+            );
+            let handles = if key.0 == a {
+                handles
+            } else {
+                [handles[1], handles[0]]
+            };
+            Curve::new(start.pos, end.pos, handles)
+        };
+        self.segment_index.insert(key, curve.aabb());
+        self.curves.insert(key, curve);
+        self.routes.get_mut().clear();
+        self.revision += 1;
+    }
+
+    fn remove_connection(&mut self, a: Id, b: Id) {
+        if !self.nodes.get(&a).unwrap().connections.contains(&b) {
+            return;
+        }
+        let key = Self::segment_key(a, b);
+        let curve = self.curves.remove(&key).unwrap();
+        self.kinds.remove(&key);
+        self.segment_index.remove(key, curve.aabb());
+        self.nodes.get_mut(&a).unwrap().connections.remove(&b);
+        self.nodes.get_mut(&b).unwrap().connections.remove(&a);
+        self.routes.get_mut().clear();
+        self.revision += 1;
+    }
+
+    /// Put a node somewhere else, its tracks keeping their handles
+    fn move_node(&mut self, id: Id, pos: vec2<f32>) {
+        let node = self.nodes.get_mut(&id).unwrap();
+        let old_pos = mem::replace(&mut node.pos, pos);
+        let connections: Vec<Id> = node.connections.iter().copied().collect();
+        self.node_index.remove(id, Aabb2::point(old_pos));
+        self.node_index.insert(id, Aabb2::point(pos));
+        for other in connections {
+            let key = Self::segment_key(id, other);
+            let curve = self.curves.remove(&key).unwrap();
+            self.segment_index.remove(key, curve.aabb());
+            let curve = Curve::new(
+                self.nodes.get(&key.0).unwrap().pos,
+                self.nodes.get(&key.1).unwrap().pos,
+                curve.handles,
+            );
+            self.segment_index.insert(key, curve.aabb());
+            self.curves.insert(key, curve);
+        }
+        self.routes.get_mut().clear();
+        self.revision += 1;
+    }
+
+    /// Insert a new node at `point`, replacing the segment with two that follow the same curve
+    fn split_segment(&mut self, point: TrackPoint, id_gen: &mut IdGen) -> Id {
+        let (curve, reversed) = self.curve(point.from, point.to);
+        let (pos, first, second) = curve.split(if reversed {
+            1.0 - point.ratio
+        } else {
+            point.ratio
+        });
+        let (a, b) = Self::segment_key(point.from, point.to);
+        let kind = self.kind(a, b);
+        self.remove_connection(a, b);
+        let node = self.add_node(TrackNode::new(id_gen, pos));
+        self.add_curve(a, node, first);
+        self.add_curve(node, b, second);
+        self.set_kind(a, node, kind);
+        self.set_kind(node, b, kind);
+        node
+    }
+
+    fn kind(&self, a: Id, b: Id) -> SegmentKind {
+        self.kinds
+            .get(&Self::segment_key(a, b))
+            .copied()
+            .unwrap_or(SegmentKind::Track)
+    }
+
+    fn set_kind(&mut self, a: Id, b: Id, kind: SegmentKind) {
+        let key = Self::segment_key(a, b);
+        if kind == SegmentKind::Track {
+            self.kinds.remove(&key);
+        } else {
+            self.kinds.insert(key, kind);
+        }
+        self.revision += 1;
+    }
+
+    fn nearest_node(&self, pos: vec2<f32>, max_distance: f32) -> Option<Id> {
+        self.node_index.nearest(pos, max_distance, |id| {
+            (self.nodes.get(&id).unwrap().pos - pos).len()
+        })
+    }
+
+    fn nearest_segment(&self, pos: vec2<f32>, max_distance: f32) -> Option<TrackPoint> {
+        let closest_point = |(from, to): (Id, Id)| TrackPoint {
+            from,
+            to,
+            ratio: self.curves[&(from, to)].closest_ratio(pos),
+        };
+        self.segment_index
+            .nearest(pos, max_distance, |segment| {
+                (self.point_pos(closest_point(segment)) - pos).len()
+            })
+            .map(closest_point)
+    }
+
+    fn nodes_in_rect(&self, rect: Aabb2<f32>) -> Vec<Id> {
+        self.node_index
+            .query(rect)
+            .into_iter()
+            .filter(|id| rect.contains(self.nodes.get(id).unwrap().pos))
+            .collect()
+    }
+
+    fn segments_in_rect(&self, rect: Aabb2<f32>) -> Vec<(Id, Id)> {
+        self.segment_index
+            .query(rect)
+            .into_iter()
+            .filter(|segment| self.curves[segment].intersects_rect(rect))
+            .collect()
     }
+
+    /// Direction a new track leaving `node` towards `target` should start in, so that it
+    /// smoothly continues one of the tracks already ending there
+    fn continuation(&self, node: Id, target: vec2<f32>) -> Option<vec2<f32>> {
+        let node = self.nodes.get(&node).unwrap();
+        let towards = (target - node.pos).normalize_or_zero();
+        node.connections
+            .iter()
+            .map(|&other| {
+                let (curve, reversed) = self.curve(node.id, other);
+                -curve.handles[reversed as usize].normalize_or_zero()
+            })
+            .filter(|&dir| vec2::dot(dir, towards) > 0.0)
+            .max_by_key(|&dir| r32(vec2::dot(dir, towards)))
+    }
+
     fn point_pos(&self, point: TrackPoint) -> vec2<f32> {
-        let from = self.nodes.get(&point.from).unwrap();
-        let to = self.nodes.get(&point.to).unwrap();
-        from.pos + (to.pos - from.pos) * point.ratio
+        if point.from == point.to {
+            return self.nodes.get(&point.from).unwrap().pos;
+        }
+        let (curve, reversed) = self.curve(point.from, point.to);
+        curve.point_at(if reversed {
+            1.0 - point.ratio
+        } else {
+            point.ratio
+        })
+    }
+
+    /// Polyline following the track between two ratios along `from`-`to`
+    fn segment_points(&self, from: Id, to: Id, from_ratio: f32, to_ratio: f32) -> Vec<vec2<f32>> {
+        if from == to {
+            return vec![self.nodes.get(&from).unwrap().pos];
+        }
+        let (curve, reversed) = self.curve(from, to);
+        if reversed {
+            curve.points_between(1.0 - from_ratio, 1.0 - to_ratio)
+        } else {
+            curve.points_between(from_ratio, to_ratio)
+        }
     }
 
     fn segment_length(&self, from: Id, to: Id) -> f32 {
-        let from = self.nodes.get(&from).unwrap();
-        let to = self.nodes.get(&to).unwrap();
-        (from.pos - to.pos).len()
+        if from == to {
+            return 0.0;
+        }
+        self.curve(from, to).0.length()
+    }
+
+    fn route_tree(&self, to: Id) -> Rc<RouteTree> {
+        if let Some(tree) = self.routes.borrow().get(&to) {
+            return tree.clone();
+        }
+        // Tracks are undirected, so searching from the target gives paths towards it
+        let next = pathfinding::directed::dijkstra::dijkstra_all(&to, |&v| {
+            let v = self.nodes.get(&v).unwrap();
+            v.connections
+                .iter()
+                .sorted()
+                .map(|&u| (u, r32(self.segment_length(v.id, u))))
+                .collect::<Vec<_>>()
+        });
+        let tree = Rc::new(RouteTree { next });
+        self.routes.borrow_mut().insert(to, tree.clone());
+        tree
     }
 
     fn pathfind(&self, from: Id, to: Id) -> Option<Vec<Id>> {
-        let to = self.nodes.get(&to).unwrap();
-        let (path, _cost) = pathfinding::directed::astar::astar(
-            &from,
-            |&v| {
-                let v = self.nodes.get(&v).unwrap();
-                v.connections.iter().copied().map(|u| {
-                    let u = self.nodes.get(&u).unwrap();
-                    (u.id, noisy_float::prelude::r32((v.pos - u.pos).len()))
-                })
-            },
-            |id| noisy_float::prelude::r32((self.nodes.get(&id).unwrap().pos - to.pos).len()),
-            |&v| v == to.id,
-        )?;
+        let tree = self.route_tree(to);
+        if from != to && !tree.next.contains_key(&from) {
+            return None;
+        }
+        let mut path = vec![from];
+        while let Some(&(next, _)) = tree.next.get(path.last().unwrap()) {
+            path.push(next);
+        }
         Some(path)
     }
 }
 
-#[derive(Deserialize, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Deserialize, Copy, Clone, PartialEq, Eq, Hash)]
 enum IoType {
     Input,
     Output,
@@ -209,6 +652,11 @@ struct FactoryIo {
     resource: Id,
     amount: Option<f32>,
     pos: vec2<f32>,
+    /// Index into [FactoryTypes::loader]
+    loader: usize,
+    sale_price: Option<f32>,
+    /// Total amount unloaded into this input
+    delivered: f32,
 }
 
 #[derive(HasId)]
@@ -217,6 +665,8 @@ struct Factory {
     ty: usize,
     pos: vec2<f32>,
     io: Vec<FactoryIo>,
+    /// Index into [MapConfig::deposit] of the ore the factory extracts
+    deposit: Option<usize>,
 }
 
 #[derive(HasId)]
@@ -228,7 +678,17 @@ struct Train {
     head: TrackPoint,
     tail_nodes: VecDeque<Id>,
     path_from_target: Option<Vec<Id>>,
-    target: Option<IoId>,
+    target: Option<Target>,
+    /// Names of the stations to visit in turn, empty for trains picking factories themselves
+    schedule: Vec<String>,
+    next_stop: usize,
+    /// Index into [TrainConfig::wagon]
+    wagon: usize,
+    /// Time spent at the current target so far
+    dwell: f32,
+    last_dwell: Option<f32>,
+    /// Time since leaving the last target
+    trip: f32,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -237,384 +697,781 @@ struct IoId {
     io: usize,
 }
 
+#[derive(Copy, Clone, Debug)]
+enum Target {
+    Io { io: IoId, node: Id },
+    Station { id: Id, node: Id },
+}
+
+impl Target {
+    fn node(&self) -> Id {
+        match *self {
+            Target::Io { node, .. } | Target::Station { node, .. } => node,
+        }
+    }
+}
+
 enum Control {
     Idle,
     Detecting {
+        button: geng::MouseButton,
         start_world_pos: vec2<f32>,
         start_screen_pos: vec2<f64>,
         start_hover: Hover,
-        timer: Timer,
     },
     MovingCamera {
         prev_pos: vec2<f32>,
     },
+    /// Dragging out a continuous track, `last` being the most recently placed node or where
+    /// the drag started while no track has been laid yet
+    LayingTrack {
+        last: Drawing,
+    },
+    /// Moving the view by dragging over the minimap
+    Minimap,
+    /// Dragging out a selection box from `start`
+    Selecting {
+        start: vec2<f32>,
+    },
+    /// Dragging the selection, grabbed at `start`
+    MovingSelection {
+        start: vec2<f32>,
+    },
+    /// Dragging a single node or factory with the move tool, grabbed at `start`
+    MovingObject {
+        grab: Grab,
+        start: vec2<f32>,
+    },
 }
 
 #[derive(HasId)]
 struct Resource {
     id: Id,
     name: String,
+    color: Rgba<f32>,
 }
 
 struct Game {
     cursor_world_position: vec2<f32>,
-    id_gen: IdGen,
     geng: Geng,
     framebuffer_size: vec2<f32>,
     camera: Camera2d,
-    config: Config,
-    factory_types: FactoryTypes,
+    config: Rc<Config>,
+    factory_types: Rc<FactoryTypes>,
+    world: World,
 
     hover: Hover,
     drawing: Option<Drawing>,
-    tracks: Tracks,
-    trains: Collection<Train>,
-    resources: Collection<Resource>,
-    factories: Collection<Factory>,
+    snapping: Snapping,
+    /// Text shown in the HUD and the time left to show it
+    message: Option<(String, f32)>,
+    /// Wagon type for the next spawned train
+    wagon_draft: usize,
+    /// Schedule for the next spawned train
+    schedule_draft: Vec<String>,
+    /// Length of a simulation step
+    tick: f32,
+    /// Real time not yet simulated
+    accumulated_time: f32,
+    /// Commands made so far and the file they are saved to
+    recording: Option<Recording>,
+    /// Commands of the replay being played that are still to come
+    pending: VecDeque<RecordedCommand>,
+    show_statistics: bool,
+    show_controls: bool,
+    overlay: Option<Overlay>,
+    camera_control: CameraControl,
+    renderer: Renderer,
+    selection: Selection,
+    /// Blueprint being placed and how
+    blueprint: Option<(Blueprint, Placement)>,
+    /// Index of the saved blueprint to load next
+    blueprint_file: usize,
 
     control: Control,
+    /// Button that started the current drag or click
+    drag_button: Option<geng::MouseButton>,
 }
 
 impl Game {
-    async fn new(geng: &Geng) -> Self {
-        let config: Config = file::load_detect(run_dir().join("assets").join("config.toml"))
-            .await
-            .unwrap();
-        let factory_types: FactoryTypes =
-            file::load_detect(run_dir().join("assets").join("factories.toml"))
-                .await
-                .unwrap();
+    async fn new(
+        geng: &Geng,
+        seed: Option<u64>,
+        scenario: Option<PathBuf>,
+        record: Option<PathBuf>,
+        replay: Option<PathBuf>,
+    ) -> Self {
+        let replay: Option<Replay> = match replay {
+            Some(path) => Some(file::load_detect(path).await.unwrap()),
+            None => None,
+        };
+        let (seed, scenario) = match &replay {
+            Some(replay) => (Some(replay.seed), replay.scenario.clone()),
+            None => (seed, scenario),
+        };
+        let world = World::load(&run_dir().join("assets"), seed, scenario.as_deref()).await;
+        let tick = replay
+            .as_ref()
+            .map_or(world.config.simulation.tick, |replay| replay.tick);
+        let pending: VecDeque<RecordedCommand> = replay
+            .map(|replay| replay.commands.into())
+            .unwrap_or_default();
+        // A recorded replay continues with the commands of the one being played
+        let recording = record.map(|path| {
+            let mut replay = Replay::new(&world, scenario, tick);
+            replay.commands = pending.iter().cloned().collect();
+            Recording::new(path, replay)
+        });
         Self {
             cursor_world_position: vec2::ZERO,
-            id_gen: IdGen::new(),
             geng: geng.clone(),
             framebuffer_size: vec2::splat(1.0),
             camera: Camera2d {
                 center: vec2::ZERO,
                 rotation: Angle::ZERO,
-                fov: Camera2dFov::MinSide(config.fov.default),
+                fov: Camera2dFov::MinSide(world.config.fov.default),
             },
-            config,
+            config: world.config.clone(),
+            factory_types: world.factory_types.clone(),
+            world,
             drawing: None,
+            snapping: Snapping::default(),
             hover: Hover::Nothing { pos: vec2::ZERO },
-            factory_types,
-
-            tracks: Tracks::default(),
-            trains: Collection::new(),
             control: Control::Idle,
-            resources: default(),
-            factories: default(),
+            drag_button: None,
+            camera_control: CameraControl::default(),
+            renderer: Renderer::new(geng),
+            selection: Selection::default(),
+            blueprint: None,
+            blueprint_file: 0,
+            message: None,
+            wagon_draft: 0,
+            schedule_draft: Vec::new(),
+            tick,
+            accumulated_time: 0.0,
+            recording,
+            pending,
+            show_statistics: false,
+            show_controls: false,
+            overlay: None,
         }
     }
-    fn spawn_factory(&mut self, pos: vec2<f32>, angle: Angle<f32>, factory_type_index: usize) {
-        let Some(factory_type) = self.factory_types.get(factory_type_index) else {
-            return;
+    /// World distance covered by `distance` measured in `target_window_height` units on screen
+    fn ui_to_world_distance(&self, distance: f32) -> f32 {
+        let pixels = distance * self.framebuffer_size.y / self.config.control.target_window_height;
+        let a = self
+            .camera
+            .screen_to_world(self.framebuffer_size, vec2::ZERO);
+        let b = self
+            .camera
+            .screen_to_world(self.framebuffer_size, vec2(pixels, 0.0));
+        (b - a).len()
+    }
+    /// Where the track being drawn starts and the direction of the track it continues
+    fn drawing_origin(&self) -> Option<(vec2<f32>, Option<vec2<f32>>)> {
+        match self.drawing? {
+            Drawing::FromScratch { start } => Some((start, None)),
+            Drawing::FromNode { id } => Some((
+                self.world.tracks.nodes.get(&id).unwrap().pos,
+                self.world
+                    .tracks
+                    .continuation(id, self.cursor_world_position),
+            )),
+        }
+    }
+    fn update_hover(&mut self) {
+        let pos = self.cursor_world_position;
+        let snap_distance = self.ui_to_world_distance(self.config.control.snap_distance);
+        self.hover = match self.world.tracks.nearest_node(pos, snap_distance) {
+            Some(id) => Hover::TrackNode { id },
+            None => Hover::Nothing {
+                pos: self
+                    .snapping
+                    .apply(&self.config.drawing.snapping, pos, self.drawing_origin()),
+            },
         };
-        let factory = Factory {
-            ty: factory_type_index,
-            id: self.id_gen.gen(),
-            pos,
-            io: factory_type
-                .io
-                .iter()
-                .enumerate()
-                .map(|(index, io)| {
-                    let io_pos = pos
-                        + vec2(factory_type.radius, 0.0).rotate(
-                            angle
-                                + Angle::from_degrees(
-                                    360.0 * index as f32 / factory_type.io.len() as f32,
-                                ),
-                        );
-                    let node = TrackNode::new(&mut self.id_gen, io_pos);
-                    let node_id = node.id;
-                    self.tracks.nodes.insert(node);
-                    FactoryIo {
-                        ty: io.r#type,
-                        node: node_id,
-                        amount: io.speed.is_some().then_some(0.0),
-                        resource: {
-                            let existing = self
-                                .resources
-                                .iter()
-                                .find(|resource| resource.name == io.resource);
-                            if let Some(existing) = existing {
-                                existing.id
-                            } else {
-                                let id = self.id_gen.gen();
-                                self.resources.insert(Resource {
-                                    id,
-                                    name: io.resource.clone(),
-                                });
-                                id
-                            }
-                        },
-                        pos: io_pos,
+    }
+    /// Ends and curve handles of the track that the current drawing would place
+    fn planned_track(&self, drawing: Drawing) -> (vec2<f32>, vec2<f32>, [vec2<f32>; 2]) {
+        let (start, start_node) = match drawing {
+            Drawing::FromScratch { start } => (start, None),
+            Drawing::FromNode { id } => (self.world.tracks.nodes.get(&id).unwrap().pos, Some(id)),
+        };
+        let (end, end_node) = match self.hover {
+            Hover::Nothing { pos } => (pos, None),
+            Hover::TrackNode { id } => (self.world.tracks.nodes.get(&id).unwrap().pos, Some(id)),
+        };
+        let start_dir = start_node.and_then(|id| self.world.tracks.continuation(id, end));
+        let end_dir = end_node.and_then(|id| self.world.tracks.continuation(id, start));
+        (start, end, curve_handles(start, start_dir, end, end_dir))
+    }
+    fn click(&mut self, hover: Hover) {
+        if self.blueprint.is_some() {
+            self.paste_blueprint();
+            return;
+        }
+        // The first click after selecting only drops the selection
+        if !self.selection.is_empty() {
+            self.selection = Selection::default();
+            return;
+        }
+        match self.drawing {
+            None => match hover {
+                Hover::Nothing { pos } => self.drawing = Some(Drawing::FromScratch { start: pos }),
+                Hover::TrackNode { id } => self.drawing = Some(Drawing::FromNode { id }),
+            },
+            Some(drawing) => {
+                let (_, _, handles) = self.planned_track(drawing);
+                if let Some(end) = self.command(Command::BuildTrack {
+                    start: drawing.track_end(),
+                    end: self.hover.track_end(),
+                    handles,
+                }) {
+                    self.drawing = Some(Drawing::FromNode { id: end });
+                }
+            }
+        }
+    }
+    /// Continue a dragged track from `last` towards the hovered position, placing a node
+    /// once the pending piece gets long or bends too much, or unconditionally on `finish`.
+    /// Returns where the track now ends.
+    fn extend_laid_track(&mut self, last: Drawing, finish: bool) -> Drawing {
+        let config = &self.config.drawing;
+        let (start, end, handles) = self.planned_track(last);
+        let distance = (end - start).len();
+        let place = match self.hover {
+            Hover::TrackNode { id } => {
+                !matches!(last, Drawing::FromNode { id: last } if last == id)
+            }
+            Hover::Nothing { .. } if distance < config.drag_min_spacing => false,
+            Hover::Nothing { .. } => {
+                let turn = match last {
+                    Drawing::FromNode { id }
+                        if !self
+                            .world
+                            .tracks
+                            .nodes
+                            .get(&id)
+                            .unwrap()
+                            .connections
+                            .is_empty() =>
+                    {
+                        self.world.tracks.continuation(id, end).map_or(
+                            std::f32::consts::PI,
+                            |dir| {
+                                vec2::dot(dir, (end - start) / distance)
+                                    .clamp(-1.0, 1.0)
+                                    .acos()
+                            },
+                        )
                     }
-                })
-                .collect(),
+                    _ => 0.0,
+                };
+                finish
+                    || distance >= config.drag_spacing
+                    || turn >= config.drag_max_turn.to_radians()
+            }
         };
-        self.factories.insert(factory);
+        if !place {
+            return last;
+        }
+        // An illegal piece keeps growing until it becomes buildable, e.g. reaches the
+        // other bank of a river as a bridge
+        if self.world.plan_track(start, end, handles).is_err() {
+            return last;
+        }
+        self.command(Command::BuildTrack {
+            start: last.track_end(),
+            end: self.hover.track_end(),
+            handles,
+        })
+        .map_or(last, |id| Drawing::FromNode { id })
+    }
+    fn spawn_factory(&mut self, factory_type_index: usize) {
+        self.command(Command::SpawnFactory {
+            pos: self.cursor_world_position,
+            angle: thread_rng().gen_range(0.0..360.0),
+            ty: factory_type_index,
+        });
     }
     fn spawn_train(&mut self) {
-        let Some(resource) = self.resources.iter().choose(&mut thread_rng()) else {
+        let spawned = self.command(Command::SpawnTrain {
+            wagon: self.wagon_draft,
+            schedule: self.schedule_draft.clone(),
+        });
+        if spawned.is_some() {
+            self.schedule_draft.clear();
+        }
+    }
+    /// Put a station at the hovered node, or on the nearest track
+    fn place_station(&mut self) {
+        let site = match self.hover {
+            Hover::TrackNode { id } => StationSite::Node(id),
+            Hover::Nothing { .. } => {
+                let snap_distance = self.ui_to_world_distance(self.config.control.snap_distance);
+                let Some(point) = self
+                    .world
+                    .tracks
+                    .nearest_segment(self.cursor_world_position, snap_distance)
+                else {
+                    return;
+                };
+                StationSite::Track(point)
+            }
+        };
+        self.command(Command::PlaceStation { site });
+        self.update_hover();
+    }
+    fn hovered_station(&self) -> Option<&Station> {
+        let Hover::TrackNode { id } = self.hover else {
+            return None;
+        };
+        self.world
+            .stations
+            .iter()
+            .find(|station| station.node == id)
+    }
+    /// Train whose head is closest to the cursor, within the snap distance
+    fn hovered_train(&self) -> Option<&Train> {
+        let snap_distance = self.ui_to_world_distance(self.config.control.snap_distance);
+        self.world
+            .trains
+            .iter()
+            .map(|train| {
+                let distance =
+                    (self.world.tracks.point_pos(train.head) - self.cursor_world_position).len();
+                (train, distance)
+            })
+            .filter(|&(_, distance)| distance <= snap_distance)
+            .min_by_key(|&(_, distance)| r32(distance))
+            .map(|(train, _)| train)
+    }
+    /// Move the loader of the hovered station or factory io one tier up
+    fn upgrade_loader(&mut self) {
+        let Hover::TrackNode { id } = self.hover else {
             return;
         };
-        if let Some(node) = self.tracks.nodes.iter().choose(&mut thread_rng()) {
-            let id = self.id_gen.gen();
-            let train = Train {
-                target: None,
-                id,
-                length: self.config.test.train_length,
-                resource: resource.id,
-                amount: 0.0,
-                head: TrackPoint {
-                    from: node.id,
-                    to: node.id,
-                    ratio: 0.0,
-                },
-                tail_nodes: default(),
-                path_from_target: None,
+        self.command(Command::UpgradeLoader { node: id });
+    }
+    fn show_message(&mut self, message: String) {
+        self.message = Some((message, self.config.hud.message_duration));
+    }
+    /// Apply and record a player command, telling the player if it failed.
+    /// Returns the node or train it created.
+    fn command(&mut self, command: Command) -> Option<Id> {
+        if !self.pending.is_empty() {
+            self.show_message("Wait for the replay to finish".to_owned());
+            return None;
+        }
+        let result = self.world.execute(&command);
+        self.selection.retain_existing(&self.world);
+        if let Some(recording) = &mut self.recording {
+            // Failed commands too, they may still have used the rng
+            let recorded = RecordedCommand {
+                tick: self.world.tick,
+                command,
             };
-            self.trains.insert(train);
+            recording.record(recorded, self.config.simulation.replay_save_interval);
         }
+        result
+            .map_err(|message| self.show_message(message))
+            .ok()
+            .flatten()
     }
-}
-
-impl geng::State for Game {
-    fn update(&mut self, delta_time: f64) {
-        let delta_time = delta_time as f32;
-        if let Control::Detecting {
-            start_world_pos,
-            ref timer,
-            ..
-        } = self.control
+    fn hud_lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("Balance: ${:.0}", self.world.wallet.balance()),
+            format!("Wagon: {}", self.config.train.wagon[self.wagon_draft].name),
+        ];
+        if let Some(name) = &self.world.scenario_name {
+            lines.push(name.clone());
+        }
+        for goal in &self.world.goals {
+            lines.push(format!(
+                "{}: {:.0}/{}",
+                goal.describe(),
+                self.world.goal_progress(goal).min(goal.target()),
+                goal.target()
+            ));
+        }
+        if let Some(limit) = self.world.time_limit {
+            let left = (limit - self.world.time).max(0.0) as u32;
+            lines.push(format!("Time left: {}:{:02}", left / 60, left % 60));
+        }
+        if let Some(overlay) = self.overlay {
+            lines.push(self.overlay_legend(overlay));
+        }
+        if !self.selection.is_empty() {
+            lines.push(self.selection.describe());
+        }
+        if self.blueprint.is_some() {
+            let controls = &self.config.controls;
+            lines.push(format!(
+                "Blueprint: {} to paste, {} rotate, {} mirror, {} save",
+                controls.describe(Action::Build),
+                controls.describe(Action::RotateBlueprint),
+                controls.describe(Action::MirrorBlueprint),
+                controls.describe(Action::SaveBlueprint),
+            ));
+        }
+        if let Some(train) = self.camera_control.follow {
+            lines.push(format!("Following train {}", train.0));
+        }
+        if !self.pending.is_empty() {
+            lines.push(format!("Replaying, {} commands left", self.pending.len()));
+        }
+        if let Some((message, _)) = &self.message {
+            lines.push(message.clone());
+        }
+        if self.show_controls {
+            lines.push(String::new());
+            lines.extend(self.controls_lines());
+        } else {
+            lines.push(format!(
+                "{}: show controls",
+                self.config.controls.describe(Action::ShowControls)
+            ));
+        }
+        if let Some(deposit) = self
+            .world
+            .map
+            .cell_at(self.cursor_world_position)
+            .and_then(|cell| cell.deposit.as_ref())
+            .filter(|deposit| deposit.amount > 0.0)
         {
-            if timer.elapsed().as_secs_f64() > self.config.control.drag_timer {
-                self.control = Control::MovingCamera {
-                    prev_pos: start_world_pos,
-                };
-            }
+            lines.push(format!(
+                "{} deposit: {:.0}",
+                self.config.map.deposit[deposit.ty].resource, deposit.amount
+            ));
+        }
+        if let Some(drawing) = self.drawing {
+            let (start, end, handles) = self.planned_track(drawing);
+            lines.push(match self.world.plan_track(start, end, handles) {
+                Ok(plan) if plan.kind == SegmentKind::Track => format!("Track: ${:.0}", plan.price),
+                Ok(plan) => format!("{:?}: ${:.0}", plan.kind, plan.price),
+                Err(reason) => reason,
+            });
+        }
+        if !self.schedule_draft.is_empty() {
+            lines.push(format!("Schedule: {}", self.schedule_draft.join(" -> ")));
         }
 
-        for factory in &mut self.factories {
-            let factory_type = &self.factory_types[factory.ty];
-            let mut max_input_dt = delta_time;
-            for (io, io_config) in factory.io.iter().zip(&factory_type.io) {
-                if io.ty == IoType::Input {
-                    if let (Some(amount), Some(speed)) = (io.amount, io_config.speed) {
-                        max_input_dt = max_input_dt.min(amount / speed);
-                    }
-                }
-            }
-            for (io, io_config) in factory.io.iter_mut().zip(&factory_type.io) {
-                match io.ty {
-                    IoType::Input => {
-                        if let (Some(amount), Some(speed)) = (&mut io.amount, io_config.speed) {
-                            *amount = (*amount - speed * max_input_dt).max(0.0);
-                        }
-                    }
-                    IoType::Output => {
-                        if let (Some(amount), Some(speed)) = (&mut io.amount, io_config.speed) {
-                            *amount += speed * max_input_dt;
-                        }
-                    }
-                }
+        if let Some(train) = self.hovered_train() {
+            lines.push(String::new());
+            lines.push(format!(
+                "Train {} ({})",
+                train.id.0, self.config.train.wagon[train.wagon].name
+            ));
+            lines.push(format!(
+                "Cargo: {:.1}/{} {}",
+                train.amount,
+                self.config.train.capacity,
+                self.world.resources.get(&train.resource).unwrap().name
+            ));
+            if let Some(target) = train.target {
+                lines.push(format!("Target: {}", self.world.target_name(target)));
             }
+            let last_dwell = train
+                .last_dwell
+                .map_or(String::new(), |dwell| format!(", last {dwell:.1}s"));
+            lines.push(format!("Dwell: {:.1}s{last_dwell}", train.dwell));
         }
 
-        for train in &mut self.trains {
-            if train.path_from_target.is_some() {
-                continue;
-            }
-            let mut go = false;
-            if let Some(io) = train.target {
-                let io = &mut self.factories.get_mut(&io.factory).unwrap().io[io.io];
-                match io.ty {
-                    IoType::Input => {
-                        let unload_amount = train
-                            .amount
-                            .min(self.config.test.train_load_speed * delta_time);
-                        train.amount -= unload_amount;
-                        if let Some(io_amount) = &mut io.amount {
-                            *io_amount += unload_amount;
-                        }
-                        if train.amount.approx_eq(&0.0) {
-                            go = true;
-                        }
-                    }
-                    IoType::Output => {
-                        let mut load_amount = (self.config.train.capacity - train.amount)
-                            .min(self.config.test.train_load_speed * delta_time);
-                        if let Some(io_amount) = io.amount {
-                            load_amount = load_amount.min(io_amount);
-                        }
-                        train.amount += load_amount;
-                        if let Some(io_amount) = &mut io.amount {
-                            *io_amount -= load_amount;
-                        }
-                        if self.config.train.capacity.approx_eq(&train.amount) {
-                            go = true;
-                        }
-                    }
+        if let Hover::TrackNode { id } = self.hover {
+            let name = self
+                .world
+                .stations
+                .iter()
+                .find(|station| station.node == id)
+                .map(|station| (station.name.clone(), station.loader))
+                .or_else(|| {
+                    self.world.factories.iter().find_map(|factory| {
+                        let (index, io) = factory.io.iter().find_position(|io| io.node == id)?;
+                        let target = Target::Io {
+                            io: IoId {
+                                factory: factory.id,
+                                io: index,
+                            },
+                            node: id,
+                        };
+                        Some((self.world.target_name(target), io.loader))
+                    })
+                });
+            if let Some((name, loader)) = name {
+                let stats = self.world.station_stats.get(&id);
+                let loaded = stats.map_or(0.0, |stats| stats.loaded);
+                let unloaded = stats.map_or(0.0, |stats| stats.unloaded);
+                let visits = stats.map_or(0, |stats| stats.visits);
+                let average_dwell = stats
+                    .filter(|stats| stats.visits > 0)
+                    .map_or(0.0, |stats| stats.dwell / stats.visits as f32);
+                lines.push(String::new());
+                lines.push(name);
+                lines.push(format!(
+                    "Loader: {}",
+                    self.factory_types.loader[loader].name
+                ));
+                lines.push(format!("Loaded {loaded:.1}, unloaded {unloaded:.1}"));
+                if let Some(stats) = stats.filter(|stats| stats.revenue > 0.0) {
+                    lines.push(format!("Revenue: ${:.0}", stats.revenue));
                 }
-            } else {
-                go = true;
+                lines.push(format!(
+                    "Throughput: {:.1}/min",
+                    (loaded + unloaded) / self.world.time.max(1.0) * 60.0
+                ));
+                lines.push(format!(
+                    "Visits: {visits}, average dwell {average_dwell:.1}s"
+                ));
             }
-
-            if go {
-                let look_for = if train.amount > self.config.train.capacity / 2.0 {
-                    IoType::Input
-                } else {
-                    IoType::Output
-                };
-                let target = self
-                    .factories
-                    .iter()
-                    .flat_map(|factory| {
-                        factory
-                            .io
-                            .iter()
-                            .enumerate()
-                            .map(|(index, io)| (factory.id, index, io))
-                    })
-                    .filter(|(_, _, io)| io.ty == look_for && io.resource == train.resource)
-                    .choose(&mut thread_rng());
-                if let Some((factory_id, io_index, io)) = target {
-                    train.path_from_target = self.tracks.pathfind(io.node, train.head.to);
-                    if train.path_from_target.is_some() {
-                        train.target = Some(IoId {
-                            factory: factory_id,
-                            io: io_index,
-                        })
-                    }
+        }
+        lines
+    }
+    /// Victory or defeat screen on top of everything
+    fn draw_outcome(&self, framebuffer: &mut ugli::Framebuffer, outcome: Outcome) {
+        let config = &self.config.hud;
+        let height = self.config.control.target_window_height;
+        let camera = self.ui_camera();
+        let width = height * self.framebuffer_size.x / self.framebuffer_size.y;
+        self.geng.draw2d().draw2d(
+            framebuffer,
+            &camera,
+            &draw2d::Quad::new(
+                Aabb2::ZERO.extend_symmetric(vec2(width, height) / 2.0),
+                config.overlay_color,
+            ),
+        );
+        let (title, subtitle) = match outcome {
+            Outcome::Victory => ("Victory!", "All goals completed"),
+            Outcome::Defeat => ("Defeat", "Ran out of time"),
+        };
+        for (text, size, y) in [
+            (title, config.title_size, config.title_size / 2.0),
+            (subtitle, config.text_size, -config.text_size),
+        ] {
+            self.geng.default_font().draw(
+                framebuffer,
+                &camera,
+                text,
+                vec2::splat(geng::TextAlign::CENTER),
+                mat3::translate(vec2(0.0, y)) * mat3::scale_uniform(size),
+                config.color,
+            );
+        }
+    }
+    /// Screen space camera for the HUD and other overlays
+    fn ui_camera(&self) -> Camera2d {
+        Camera2d {
+            center: vec2::ZERO,
+            rotation: Angle::ZERO,
+            fov: Camera2dFov::Vertical(self.config.control.target_window_height),
+        }
+    }
+    /// Resources in a stable order, for graph legends and CSV columns
+    fn sorted_resources(&self) -> Vec<&Resource> {
+        self.world
+            .resources
+            .iter()
+            .sorted_by_key(|resource| resource.id)
+            .collect()
+    }
+    fn export_statistics(&mut self) {
+        let path = &self.config.statistics.export_path;
+        let csv = self.world.statistics.to_csv(&self.sorted_resources());
+        let message = match std::fs::write(path, csv) {
+            Ok(()) => format!("Statistics exported to {}", path.display()),
+            Err(error) => format!("Failed to export statistics: {error}"),
+        };
+        self.show_message(message);
+    }
+    /// Line graphs stacked along the right edge of the screen
+    fn draw_statistics(&self, framebuffer: &mut ugli::Framebuffer) {
+        let config = &self.config.statistics;
+        let hud = &self.config.hud;
+        let height = self.config.control.target_window_height;
+        let camera = self.ui_camera();
+        let width = height * self.framebuffer_size.x / self.framebuffer_size.y;
+        let top_right = vec2(width, height) / 2.0 - vec2::splat(hud.margin);
+        let graphs = self
+            .world
+            .statistics
+            .graphs(&self.sorted_resources(), config);
+        for (i, graph) in graphs.iter().enumerate() {
+            let top = top_right.y - (config.graph_height + hud.margin) * i as f32;
+            let rect = Aabb2::from_corners(
+                vec2(top_right.x - config.graph_width, top - config.graph_height),
+                vec2(top_right.x, top),
+            );
+            self.geng.draw2d().draw2d(
+                framebuffer,
+                &camera,
+                &draw2d::Quad::new(rect, config.background_color),
+            );
+            let max = graph.max();
+            for series in &graph.series {
+                if series.values.len() < 2 {
+                    continue;
                 }
+                let step = rect.width() / (series.values.len() - 1) as f32;
+                let points = series
+                    .values
+                    .iter()
+                    .enumerate()
+                    .map(|(i, value)| rect.min + vec2(step * i as f32, rect.height() * value / max))
+                    .collect();
+                self.geng.draw2d().draw2d(
+                    framebuffer,
+                    &camera,
+                    &draw2d::Chain::new(points, config.line_width, series.color, 4),
+                );
+            }
+            for (text, align, corner) in [
+                (
+                    graph.title.clone(),
+                    geng::TextAlign::LEFT,
+                    vec2(rect.min.x, rect.max.y),
+                ),
+                (format!("{max:.0}"), geng::TextAlign::RIGHT, rect.max),
+            ] {
+                self.geng.default_font().draw(
+                    framebuffer,
+                    &camera,
+                    &text,
+                    vec2(align, geng::TextAlign::TOP),
+                    mat3::translate(corner) * mat3::scale_uniform(hud.text_size / 2.0),
+                    hud.color,
+                );
             }
         }
+    }
+    /// Text lines in the top left corner of the screen
+    fn draw_hud(&self, framebuffer: &mut ugli::Framebuffer, lines: &[String]) {
+        let config = &self.config.hud;
+        let height = self.config.control.target_window_height;
+        let camera = self.ui_camera();
+        let width = height * self.framebuffer_size.x / self.framebuffer_size.y;
+        let top_left = vec2(-width, height) / 2.0 + vec2(config.margin, -config.margin);
+        for (i, line) in lines.iter().enumerate() {
+            self.geng.default_font().draw(
+                framebuffer,
+                &camera,
+                line,
+                vec2(geng::TextAlign::LEFT, geng::TextAlign::TOP),
+                mat3::translate(top_left - vec2(0.0, config.text_size * i as f32))
+                    * mat3::scale_uniform(config.text_size),
+                config.color,
+            );
+        }
+    }
+}
 
-        for train in &mut self.trains {
-            let Some(path) = &mut train.path_from_target else {
-                continue;
-            };
-            while path.last() == Some(&train.head.to) {
-                path.pop();
+impl geng::State for Game {
+    fn update(&mut self, delta_time: f64) {
+        let delta_time = delta_time as f32;
+        if let Some((_, time_left)) = &mut self.message {
+            *time_left -= delta_time;
+            if *time_left <= 0.0 {
+                self.message = None;
             }
-            let from = self.tracks.nodes.get(&train.head.from).unwrap();
-            let to = self.tracks.nodes.get(&train.head.to).unwrap();
-            let current_segment_length = self.tracks.segment_length(from.id, to.id);
-            let mut current_segment_progress = train.head.ratio * current_segment_length;
-            current_segment_progress += self.config.test.train_speed * delta_time;
-            if current_segment_progress < current_segment_length {
-                train.head.ratio = current_segment_progress / current_segment_length;
-            } else if let Some(next_node) = path.pop() {
-                let next_node = self.tracks.nodes.get(&next_node).unwrap();
-                let next_segment_length = self.tracks.segment_length(to.id, next_node.id);
-                let next_segment_progress = current_segment_progress - current_segment_length;
-                train.head = TrackPoint {
-                    from: to.id,
-                    to: next_node.id,
-                    ratio: next_segment_progress / next_segment_length,
-                };
-                train.tail_nodes.push_front(to.id);
-
-                let mut covered_length = next_segment_progress;
-                for (i, (a, b)) in train.tail_nodes.iter().copied().tuple_windows().enumerate() {
-                    if covered_length > train.length {
-                        train.tail_nodes.truncate(i + 1);
-                        break;
-                    }
-                    covered_length += self.tracks.segment_length(a, b);
-                }
-            } else {
-                train.head.ratio = 1.0;
-                train.path_from_target = None;
+        }
+        // The world always steps by the same amount, so that replays play out the same
+        self.accumulated_time = (self.accumulated_time + delta_time)
+            .min(self.tick * self.config.simulation.max_steps as f32);
+        while self.accumulated_time >= self.tick {
+            self.accumulated_time -= self.tick;
+            execute_due(&mut self.world, &mut self.pending);
+            self.world.update(self.tick);
+        }
+        if let Some(recording) = &mut self.recording {
+            if let Err(error) = recording.update(delta_time) {
+                self.show_message(format!("Failed to save the replay: {error}"));
             }
         }
+        self.update_camera(delta_time);
     }
     fn handle_event(&mut self, event: geng::Event) {
         match event {
-            geng::Event::KeyPress { key } => match key {
-                geng::Key::Space => {
-                    self.spawn_train();
-                }
-                geng::Key::Digit0 => {
-                    self.spawn_factory(self.cursor_world_position, thread_rng().gen(), 0);
-                }
-                geng::Key::Digit1 => {
-                    self.spawn_factory(self.cursor_world_position, thread_rng().gen(), 1);
-                }
-                geng::Key::Digit2 => {
-                    self.spawn_factory(self.cursor_world_position, thread_rng().gen(), 2);
+            geng::Event::KeyPress { key } => {
+                for action in self.actions(Input::Key(key)) {
+                    self.perform(action);
                 }
-                geng::Key::Digit3 => {
-                    self.spawn_factory(self.cursor_world_position, thread_rng().gen(), 3);
-                }
-                geng::Key::Digit4 => {
-                    self.spawn_factory(self.cursor_world_position, thread_rng().gen(), 4);
-                }
-                _ => {}
-            },
-            geng::Event::MousePress {
-                button: geng::MouseButton::Left,
-            } => {
+            }
+            geng::Event::MousePress { button } => {
                 let position = self.geng.window().cursor_position().unwrap_or(vec2::ZERO);
+                self.drag_button = Some(button);
+                if let Some(pos) = self
+                    .minimap_pick(position)
+                    .filter(|_| self.actions(Input::Mouse(button)).contains(&Action::Build))
+                {
+                    self.release_camera();
+                    self.camera.center = pos;
+                    self.control = Control::Minimap;
+                    return;
+                }
                 let world_pos = self
                     .camera
                     .screen_to_world(self.framebuffer_size, position.map(|x| x as f32));
                 self.control = Control::Detecting {
+                    button,
                     start_world_pos: world_pos,
                     start_screen_pos: position,
                     start_hover: self.hover,
-                    timer: Timer::new(),
                 }
             }
-            geng::Event::MouseRelease {
-                button: geng::MouseButton::Left,
-            } => match mem::replace(&mut self.control, Control::Idle) {
-                Control::Idle => {}
-                Control::MovingCamera { prev_pos: _ } => {}
-                Control::Detecting { start_hover, .. } => match self.drawing {
-                    None => match start_hover {
-                        Hover::Nothing { pos } => {
-                            self.drawing = Some(Drawing::FromScratch { start: pos })
+            geng::Event::MouseRelease { button } => {
+                // Only the button that started the drag ends it
+                if self.drag_button == Some(button) {
+                    self.drag_button = None;
+                    match mem::replace(&mut self.control, Control::Idle) {
+                        Control::Detecting { start_hover, .. } => {
+                            self.click_button(button, start_hover);
+                        }
+                        Control::LayingTrack { last } => {
+                            self.extend_laid_track(last, true);
+                            self.drawing = None;
                         }
-                        Hover::TrackNode { id } => {
-                            self.drawing = Some(Drawing::FromNode { id });
+                        Control::Selecting { start } => {
+                            self.selection = self.world.select(self.selection_rect(start));
                         }
-                    },
-                    Some(drawing) => {
-                        let start = match drawing {
-                            Drawing::FromScratch { start } => {
-                                let node = TrackNode::new(&mut self.id_gen, start);
-                                let id = node.id;
-                                self.tracks.nodes.insert(node);
-                                id
+                        Control::MovingSelection { start } => {
+                            let offset = self.selection_offset(start);
+                            if offset != vec2::ZERO {
+                                self.command(Command::Move {
+                                    selection: self.selection.clone(),
+                                    offset,
+                                });
                             }
-                            Drawing::FromNode { id } => id,
-                        };
-                        let end = match self.hover {
-                            Hover::Nothing { pos } => {
-                                let node = TrackNode::new(&mut self.id_gen, pos);
-                                let id = node.id;
-                                self.tracks.nodes.insert(node);
-                                id
+                        }
+                        Control::MovingObject { grab, start } => {
+                            let offset = self.grab_offset(grab, start);
+                            if offset != vec2::ZERO {
+                                self.command(Command::Move {
+                                    selection: grab.selection(),
+                                    offset,
+                                });
                             }
-                            Hover::TrackNode { id } => id,
-                        };
-                        self.tracks.add_connection(start, end);
-                        self.drawing = Some(Drawing::FromNode { id: end });
+                        }
+                        Control::Idle | Control::MovingCamera { .. } | Control::Minimap => {}
                     }
-                },
-            },
+                }
+                self.update_hover();
+            }
             geng::Event::Wheel { delta } => {
+                let position = self.geng.window().cursor_position().unwrap_or(vec2::ZERO);
+                let position = position.map(|x| x as f32);
+                let anchor = self.camera.screen_to_world(self.framebuffer_size, position);
                 let fov = self.camera.fov.value_mut();
                 *fov = (*fov * self.config.control.zoom_speed.powf(-delta as f32))
                     .clamp(self.config.fov.min, self.config.fov.max);
-            }
-            geng::Event::MousePress {
-                button: geng::MouseButton::Right,
-            } => {
-                self.drawing = None;
+                // Keep the point under the cursor in place, unless a train is being followed
+                if self.camera_control.follow.is_none() {
+                    let cursor_world_pos =
+                        self.camera.screen_to_world(self.framebuffer_size, position);
+                    self.camera.center += anchor - cursor_world_pos;
+                    self.camera_control.transition = None;
+                }
+                self.cursor_world_position =
+                    self.camera.screen_to_world(self.framebuffer_size, position);
+                self.update_hover();
             }
             geng::Event::CursorMove {
                 position: cursor_screen_position,
@@ -624,11 +1481,15 @@ impl geng::State for Game {
                     cursor_screen_position.map(|x| x as f32),
                 );
                 self.cursor_world_position = cursor_world_pos;
+                let blueprint_pos = self.blueprint_pos();
+                if let Some((_, placement)) = &mut self.blueprint {
+                    placement.pos = blueprint_pos;
+                }
                 if let Control::Detecting {
+                    button,
                     start_world_pos,
                     start_screen_pos,
-                    start_hover: _,
-                    timer: _,
+                    start_hover,
                 } = self.control
                 {
                     if (cursor_screen_position - start_screen_pos).len() as f32
@@ -636,38 +1497,68 @@ impl geng::State for Game {
                         / self.framebuffer_size.y
                         > self.config.control.min_drag_distance
                     {
-                        self.control = Control::MovingCamera {
-                            prev_pos: start_world_pos,
-                        }
+                        let actions = self.actions(Input::Mouse(button));
+                        let grab = if actions.contains(&Action::MoveObject) {
+                            self.grab_at(start_world_pos)
+                        } else {
+                            None
+                        };
+                        self.control = if actions.contains(&Action::Select) {
+                            Control::Selecting {
+                                start: start_world_pos,
+                            }
+                        } else if let Some(grab) = grab {
+                            Control::MovingObject {
+                                grab,
+                                start: start_world_pos,
+                            }
+                        } else if actions.contains(&Action::Build)
+                            && self.grabs_selection(start_world_pos)
+                        {
+                            Control::MovingSelection {
+                                start: start_world_pos,
+                            }
+                        } else if actions.contains(&Action::Build) {
+                            // A new start node only comes with the first piece of track
+                            let start = match start_hover {
+                                Hover::Nothing { pos } => Drawing::FromScratch { start: pos },
+                                Hover::TrackNode { id } => Drawing::FromNode { id },
+                            };
+                            self.drawing = Some(start);
+                            Control::LayingTrack { last: start }
+                        } else if actions.contains(&Action::PanCamera) {
+                            self.release_camera();
+                            Control::MovingCamera {
+                                prev_pos: start_world_pos,
+                            }
+                        } else {
+                            Control::Idle
+                        };
                     }
                 }
                 if let Control::MovingCamera { prev_pos } = &mut self.control {
                     self.camera.center += *prev_pos - cursor_world_pos;
                 }
+                if let Control::Minimap = self.control {
+                    let ui_pos = self.ui_camera().screen_to_world(
+                        self.framebuffer_size,
+                        cursor_screen_position.map(|x| x as f32),
+                    );
+                    // Keep following the cursor when it leaves the minimap
+                    let rect = self.minimap_rect();
+                    let ui_pos = vec2(
+                        ui_pos.x.clamp(rect.min.x, rect.max.x),
+                        ui_pos.y.clamp(rect.min.y, rect.max.y),
+                    );
+                    self.camera.center = self.minimap_to_world(ui_pos);
+                }
 
-                self.hover = Hover::Nothing {
-                    pos: cursor_world_pos,
-                };
-                if let Some(closest_node) = self
-                    .tracks
-                    .nodes
-                    .iter()
-                    .min_by_key(|node| r32((node.pos - cursor_world_pos).len()))
-                {
-                    if let Some(node_screen_pos) = self
-                        .camera
-                        .world_to_screen(self.framebuffer_size, closest_node.pos)
-                    {
-                        if (node_screen_pos - cursor_screen_position.map(|x| x as f32)).len()
-                            * self.config.control.target_window_height
-                            / self.framebuffer_size.y
-                            < self.config.control.snap_distance
-                        {
-                            self.hover = Hover::TrackNode {
-                                id: closest_node.id,
-                            };
-                        }
-                    }
+                self.update_hover();
+                if let Control::LayingTrack { last } = self.control {
+                    let last = self.extend_laid_track(last, false);
+                    self.control = Control::LayingTrack { last };
+                    self.drawing = Some(last);
+                    self.update_hover();
                 }
             }
             _ => {}
@@ -677,26 +1568,49 @@ impl geng::State for Game {
         self.framebuffer_size = framebuffer.size().map(|x| x as f32);
         ugli::clear(framebuffer, Some(self.config.background), None, None);
 
-        for factory in &self.factories {
+        let view = self.camera.view_area(self.framebuffer_size).bounding_box();
+        for (rect, cell) in self.world.map.cells_in_rect(view) {
+            let config = &self.config.map;
+            let color = cell.terrain.color(config, cell.height);
+            self.geng
+                .draw2d()
+                .draw2d(framebuffer, &self.camera, &draw2d::Quad::new(rect, color));
+            if let Some(deposit) = &cell.deposit {
+                let deposit_config = &config.deposit[deposit.ty];
+                let mut color = deposit_config.color;
+                color.a *= (deposit.amount / deposit_config.richness).min(1.0);
+                self.geng.draw2d().draw2d(
+                    framebuffer,
+                    &self.camera,
+                    &draw2d::Quad::new(rect, color),
+                );
+            }
+        }
+
+        let show_labels = self.text_visible(self.config.test.text_size);
+        let show_amounts = self.text_visible(self.config.test.amount_size);
+        for factory in &self.world.factories {
             let factory_type = &self.factory_types[factory.ty];
             self.geng.draw2d().draw2d(
                 framebuffer,
                 &self.camera,
                 &draw2d::Ellipse::circle(factory.pos, factory_type.radius, factory_type.color),
             );
-            self.geng.draw2d().draw2d(
-                framebuffer,
-                &self.camera,
-                &draw2d::Text::unit(
-                    &**self.geng.default_font(),
-                    &factory_type.name,
-                    self.config.test.text_color,
-                )
-                .fit_into(Ellipse::circle(factory.pos, factory_type.radius)),
-            );
+            if show_labels {
+                self.geng.draw2d().draw2d(
+                    framebuffer,
+                    &self.camera,
+                    &draw2d::Text::unit(
+                        &**self.geng.default_font(),
+                        &factory_type.name,
+                        self.config.test.text_color,
+                    )
+                    .fit_into(Ellipse::circle(factory.pos, factory_type.radius)),
+                );
+            }
 
             for io in &factory.io {
-                let resource = self.resources.get(&io.resource).unwrap();
+                let resource = self.world.resources.get(&io.resource).unwrap();
                 self.geng.draw2d().draw2d(
                     framebuffer,
                     &self.camera,
@@ -706,20 +1620,23 @@ impl geng::State for Game {
                         self.config.station.color,
                     ),
                 );
-                self.geng.draw2d().draw2d(
-                    framebuffer,
-                    &self.camera,
-                    &draw2d::Text::unit(
-                        &**self.geng.default_font(),
-                        &resource.name,
-                        self.config.test.text_color,
-                    )
-                    .align_bounding_box(vec2(0.5, 0.0))
-                    .transform(
-                        mat3::translate(io.pos) * mat3::scale_uniform(self.config.test.text_size),
-                    ),
-                );
-                if let Some(amount) = io.amount {
+                if show_labels {
+                    self.geng.draw2d().draw2d(
+                        framebuffer,
+                        &self.camera,
+                        &draw2d::Text::unit(
+                            &**self.geng.default_font(),
+                            &resource.name,
+                            self.config.test.text_color,
+                        )
+                        .align_bounding_box(vec2(0.5, 0.0))
+                        .transform(
+                            mat3::translate(io.pos)
+                                * mat3::scale_uniform(self.config.test.text_size),
+                        ),
+                    );
+                }
+                if let Some(amount) = io.amount.filter(|_| show_amounts) {
                     self.geng.default_font().draw(
                         framebuffer,
                         &self.camera,
@@ -732,67 +1649,56 @@ impl geng::State for Game {
             }
         }
 
-        for a in &self.tracks.nodes {
-            for b in &a.connections {
-                let b = self.tracks.nodes.get(b).unwrap();
-                if b.id.0 > a.id.0 {
-                    continue;
-                }
-                self.geng.draw2d().draw2d(
-                    framebuffer,
-                    &self.camera,
-                    &draw2d::Segment::new(
-                        Segment(a.pos, b.pos),
-                        self.config.track.width,
-                        self.config.track.color,
-                    ),
-                );
-            }
+        self.draw_tracks(framebuffer);
+
+        if let Some(overlay) = self.overlay {
+            self.draw_overlay(framebuffer, overlay);
         }
 
-        for train in &self.trains {
-            let mut pos = self.tracks.point_pos(train.head);
-            let mut draw_towards = |to_pos: vec2<f32>| {
+        for station in &self.world.stations {
+            self.geng.draw2d().draw2d(
+                framebuffer,
+                &self.camera,
+                &draw2d::Chain::new(
+                    station.platform(&self.world.tracks),
+                    self.config.station.platform_width,
+                    self.config.station.color,
+                    4,
+                ),
+            );
+            if show_labels {
                 self.geng.draw2d().draw2d(
                     framebuffer,
                     &self.camera,
-                    &draw2d::Segment::new(
-                        Segment(pos, to_pos),
-                        self.config.train.width,
-                        self.config.train.color,
+                    &draw2d::Text::unit(
+                        &**self.geng.default_font(),
+                        &station.name,
+                        self.config.test.text_color,
+                    )
+                    .align_bounding_box(vec2(0.5, 0.0))
+                    .transform(
+                        mat3::translate(self.world.tracks.nodes.get(&station.node).unwrap().pos)
+                            * mat3::scale_uniform(self.config.test.text_size),
                     ),
                 );
-                pos = to_pos;
-            };
+            }
+        }
 
-            let mut node = train.head.to;
-            let mut covered_length =
-                self.tracks.segment_length(train.head.from, train.head.to) * train.head.ratio;
-            let last_node = 'last: {
-                for (a, b) in train.tail_nodes.iter().copied().tuple_windows() {
-                    if covered_length > train.length {
-                        break 'last Some(a);
-                    }
-                    covered_length += self.tracks.segment_length(a, b);
-                    draw_towards(self.tracks.nodes.get(&a).unwrap().pos);
-                    node = a;
-                }
-                train.tail_nodes.back().copied()
+        self.draw_trains(framebuffer);
+        for train in self.world.trains.iter().filter(|_| show_amounts) {
+            let label = match train
+                .target
+                .and_then(|target| self.world.queues.position(target.node(), train.id))
+            {
+                Some(place) if place > 0 => format!("{:.1} (queued #{place})", train.amount),
+                _ => format!("{:.1}", train.amount),
             };
-            if let Some(last_node) = last_node {
-                let segment_length = self.tracks.segment_length(last_node, node);
-                draw_towards(self.tracks.point_pos(TrackPoint {
-                    from: last_node,
-                    to: node,
-                    ratio: (covered_length - train.length).max(0.0) / segment_length,
-                }));
-            }
             self.geng.default_font().draw(
                 framebuffer,
                 &self.camera,
-                &format!("{:.1}", train.amount),
+                &label,
                 vec2(geng::TextAlign::CENTER, geng::TextAlign::BOTTOM),
-                mat3::translate(self.tracks.point_pos(train.head))
+                mat3::translate(self.world.tracks.point_pos(train.head))
                     * mat3::scale_uniform(self.config.test.amount_size),
                 self.config.test.amount_color,
             );
@@ -800,42 +1706,111 @@ impl geng::State for Game {
 
         // preview
         if let Some(drawing) = self.drawing {
-            let start = match drawing {
-                Drawing::FromScratch { start } => start,
-                Drawing::FromNode { id } => self.tracks.nodes.get(&id).unwrap().pos,
-            };
-            let end = match self.hover {
-                Hover::Nothing { pos } => pos,
-                Hover::TrackNode { id } => self.tracks.nodes.get(&id).unwrap().pos,
+            let (start, end, handles) = self.planned_track(drawing);
+            let color = match self.world.plan_track(start, end, handles) {
+                Ok(_) => self.config.drawing.preview_color,
+                Err(_) => self.config.drawing.invalid_color,
             };
             self.geng.draw2d().draw2d(
                 framebuffer,
                 &self.camera,
-                &draw2d::Segment::new(
-                    Segment(start, end),
+                &draw2d::Chain::new(
+                    Curve::new(start, end, handles).points().to_vec(),
                     self.config.track.width,
-                    self.config.drawing.preview_color,
+                    color,
+                    4,
                 ),
             );
         }
         match self.hover {
-            Hover::Nothing { .. } => {}
+            Hover::Nothing { pos } => {
+                // Snapping indicator
+                if pos != self.cursor_world_position {
+                    if let Some((start, _)) = self
+                        .drawing_origin()
+                        .filter(|_| self.snapping.angle || self.snapping.length)
+                    {
+                        self.geng.draw2d().draw2d(
+                            framebuffer,
+                            &self.camera,
+                            &draw2d::Segment::new(
+                                Segment(start, start + (pos - start) * 2.0),
+                                self.config.track.width / 4.0,
+                                self.config.drawing.preview_color,
+                            ),
+                        );
+                    }
+                    self.geng.draw2d().draw2d(
+                        framebuffer,
+                        &self.camera,
+                        &draw2d::Ellipse::circle(
+                            pos,
+                            self.config.track.width / 2.0,
+                            self.config.drawing.preview_color,
+                        ),
+                    );
+                }
+            }
             Hover::TrackNode { id } => self.geng.draw2d().draw2d(
                 framebuffer,
                 &self.camera,
                 &draw2d::Ellipse::circle(
-                    self.tracks.nodes.get(&id).unwrap().pos,
+                    self.world.tracks.nodes.get(&id).unwrap().pos,
                     self.config.track.width,
                     self.config.drawing.preview_color,
                 ),
             ),
         }
+
+        self.draw_blueprint(framebuffer);
+        self.draw_selection(framebuffer);
+        self.draw_minimap(framebuffer);
+        self.draw_hud(framebuffer, &self.hud_lines());
+        if self.show_statistics {
+            self.draw_statistics(framebuffer);
+        }
+        if let Some(outcome) = self.world.outcome {
+            self.draw_outcome(framebuffer, outcome);
+        }
     }
 }
 
+#[derive(clap::Parser)]
+struct CliArgs {
+    /// Run headless benchmarks instead of the game, failing if spatial queries are too
+    /// slow. Best built with `--release`.
+    #[clap(long)]
+    bench: bool,
+    /// Map generator seed
+    #[clap(long)]
+    seed: Option<u64>,
+    /// Scenario file to play
+    #[clap(long)]
+    scenario: Option<PathBuf>,
+    /// Save every command to this file, to be played back with `--replay`
+    #[clap(long)]
+    record: Option<PathBuf>,
+    /// Play back a recorded session, overrides `--seed` and `--scenario`
+    #[clap(long)]
+    replay: Option<PathBuf>,
+    #[clap(flatten)]
+    geng: geng::CliArgs,
+}
+
 fn main() {
     geng::setup_panic_handler();
-    Geng::run("tracktorio", |geng| async move {
-        geng.run_state(Game::new(&geng).await).await
+    let cli_args: CliArgs = cli::parse();
+    if cli_args.bench {
+        bench::run();
+        return;
+    }
+    let mut options = geng::ContextOptions::default();
+    options.window.title = "tracktorio".to_owned();
+    options.with_cli(&cli_args.geng);
+    let (seed, scenario) = (cli_args.seed, cli_args.scenario);
+    let (record, replay) = (cli_args.record, cli_args.replay);
+    Geng::run_with(&options, move |geng| async move {
+        geng.run_state(Game::new(&geng, seed, scenario, record, replay).await)
+            .await
     });
 }
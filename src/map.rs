@@ -0,0 +1,317 @@
+use super::*;
+
+#[derive(Deserialize)]
+pub struct DepositConfig {
+    pub resource: String,
+    /// Typical size of a deposit field in world units
+    pub scale: f32,
+    /// Noise value above which there is ore, between 0 and 1
+    pub threshold: f32,
+    /// Amount in a cell at the center of the richest field
+    pub richness: f32,
+    pub color: Rgba<f32>,
+}
+
+#[derive(Deserialize)]
+pub struct TerrainConfig {
+    /// Height below which there is water, between 0 and 1
+    pub water_level: f32,
+    /// Height above which there are mountains, between 0 and 1
+    pub mountain_level: f32,
+    /// Typical size of a forest in world units
+    pub forest_scale: f32,
+    /// Noise value above which there is forest, between 0 and 1
+    pub forest_threshold: f32,
+    pub water_color: Rgba<f32>,
+    pub mountain_color: Rgba<f32>,
+    pub forest_color: Rgba<f32>,
+}
+
+#[derive(Deserialize)]
+pub struct MapConfig {
+    /// Used when none is given on the command line, a random one if missing
+    pub seed: Option<u64>,
+    /// Cells per side, the map is centered on the origin
+    pub size: usize,
+    pub cell_size: f32,
+    /// Typical size of hills in world units
+    pub height_scale: f32,
+    pub octaves: usize,
+    pub low_color: Rgba<f32>,
+    pub high_color: Rgba<f32>,
+    pub terrain: TerrainConfig,
+    pub deposit: Vec<DepositConfig>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Terrain {
+    Plain,
+    Forest,
+    Water,
+    Mountain,
+}
+
+impl Terrain {
+    /// Whether nodes can be placed here and ordinary tracks can cross it
+    pub fn is_buildable(self) -> bool {
+        matches!(self, Terrain::Plain | Terrain::Forest)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Terrain::Plain => "plain",
+            Terrain::Forest => "forest",
+            Terrain::Water => "water",
+            Terrain::Mountain => "mountains",
+        }
+    }
+
+    pub fn color(self, config: &MapConfig, height: f32) -> Rgba<f32> {
+        match self {
+            Terrain::Plain => Rgba::lerp(config.low_color, config.high_color, height),
+            Terrain::Forest => config.terrain.forest_color,
+            Terrain::Water => config.terrain.water_color,
+            Terrain::Mountain => config.terrain.mountain_color,
+        }
+    }
+}
+
+pub struct Deposit {
+    /// Index into [MapConfig::deposit]
+    pub ty: usize,
+    pub amount: f32,
+}
+
+pub struct MapCell {
+    /// Between 0 and 1
+    pub height: f32,
+    pub terrain: Terrain,
+    pub deposit: Option<Deposit>,
+}
+
+/// Terrain and resource deposits on a square grid
+pub struct Map {
+    cell_size: f32,
+    size: usize,
+    cells: Vec<MapCell>,
+}
+
+impl Map {
+    pub fn generate(config: &MapConfig, seed: u64) -> Self {
+        let size = config.size;
+        let mut map = Self {
+            cell_size: config.cell_size,
+            size,
+            cells: Vec::with_capacity(size * size),
+        };
+        for index in 0..size * size {
+            let pos = map.cell_rect(index).center();
+            let height = fractal_noise(seed, pos / config.height_scale, config.octaves);
+            let terrain_config = &config.terrain;
+            let terrain = if height < terrain_config.water_level {
+                Terrain::Water
+            } else if height > terrain_config.mountain_level {
+                Terrain::Mountain
+            } else {
+                let forest_seed = seed.wrapping_add(config.deposit.len() as u64 + 1);
+                let forest = fractal_noise(
+                    forest_seed,
+                    pos / terrain_config.forest_scale,
+                    config.octaves,
+                );
+                if forest > terrain_config.forest_threshold {
+                    Terrain::Forest
+                } else {
+                    Terrain::Plain
+                }
+            };
+            let deposit = config
+                .deposit
+                .iter()
+                .enumerate()
+                .filter_map(|(ty, deposit)| {
+                    // Every deposit type gets its own noise field
+                    let seed = seed.wrapping_add(ty as u64 + 1);
+                    let value = fractal_noise(seed, pos / deposit.scale, config.octaves);
+                    let density = (value - deposit.threshold) / (1.0 - deposit.threshold);
+                    (density > 0.0).then_some(Deposit {
+                        ty,
+                        amount: density * deposit.richness,
+                    })
+                })
+                .max_by_key(|deposit| r32(deposit.amount));
+            map.cells.push(MapCell {
+                height,
+                terrain,
+                deposit,
+            });
+        }
+        map
+    }
+
+    pub fn bounds(&self) -> Aabb2<f32> {
+        let half = self.size as f32 * self.cell_size / 2.0;
+        Aabb2::from_corners(vec2::splat(-half), vec2::splat(half))
+    }
+
+    fn cell_index(&self, pos: vec2<f32>) -> Option<usize> {
+        let local = (pos - self.bounds().min) / self.cell_size;
+        let (x, y) = (local.x.floor(), local.y.floor());
+        let size = self.size as f32;
+        (x >= 0.0 && y >= 0.0 && x < size && y < size).then(|| y as usize * self.size + x as usize)
+    }
+
+    fn cell_rect(&self, index: usize) -> Aabb2<f32> {
+        let min = self.bounds().min
+            + vec2((index % self.size) as f32, (index / self.size) as f32) * self.cell_size;
+        Aabb2::from_corners(min, min + vec2::splat(self.cell_size))
+    }
+
+    pub fn cell_at(&self, pos: vec2<f32>) -> Option<&MapCell> {
+        self.cell_index(pos).map(|index| &self.cells[index])
+    }
+
+    /// Make all terrain plain
+    pub fn flatten(&mut self) {
+        for cell in &mut self.cells {
+            cell.terrain = Terrain::Plain;
+        }
+    }
+
+    /// Put `amount` of ore of type `ty` into every cell within `radius` of `center`
+    pub fn add_deposit(&mut self, ty: usize, center: vec2<f32>, radius: f32, amount: f32) {
+        let rect = Aabb2::point(center).extend_uniform(radius);
+        let indices: Vec<usize> = self
+            .indices_in_rect(rect)
+            .filter(|&index| (self.cell_rect(index).center() - center).len() <= radius)
+            .collect();
+        for index in indices {
+            self.cells[index].deposit = Some(Deposit { ty, amount });
+        }
+    }
+
+    /// Terrain at `pos`, plain outside of the map
+    pub fn terrain_at(&self, pos: vec2<f32>) -> Terrain {
+        self.cell_at(pos)
+            .map_or(Terrain::Plain, |cell| cell.terrain)
+    }
+
+    /// Length of the polyline over each terrain type
+    pub fn terrain_along(&self, points: &[vec2<f32>]) -> HashMap<Terrain, f32> {
+        let mut lengths = HashMap::new();
+        // Sampled often enough not to skip over a cell
+        let step = self.cell_size / 4.0;
+        for (a, b) in points.iter().copied().tuple_windows() {
+            let length = (b - a).len();
+            let samples = (length / step).ceil().max(1.0) as usize;
+            for i in 0..samples {
+                let pos = a + (b - a) * ((i as f32 + 0.5) / samples as f32);
+                *lengths.entry(self.terrain_at(pos)).or_default() += length / samples as f32;
+            }
+        }
+        lengths
+    }
+
+    fn indices_in_rect(&self, rect: Aabb2<f32>) -> impl Iterator<Item = usize> + '_ {
+        let local = rect.translate(-self.bounds().min);
+        let cells = |min: f32, max: f32| {
+            let first = (min / self.cell_size).floor().max(0.0) as usize;
+            let last = ((max / self.cell_size).floor() + 1.0).clamp(0.0, self.size as f32) as usize;
+            first..last
+        };
+        let xs = cells(local.min.x, local.max.x);
+        let ys = cells(local.min.y, local.max.y);
+        ys.flat_map(move |y| xs.clone().map(move |x| y * self.size + x))
+    }
+
+    /// Cells overlapping `rect` together with their bounds
+    pub fn cells_in_rect(&self, rect: Aabb2<f32>) -> impl Iterator<Item = (Aabb2<f32>, &MapCell)> {
+        self.indices_in_rect(rect)
+            .map(|index| (self.cell_rect(index), &self.cells[index]))
+    }
+
+    fn deposit_cells(&self, ty: usize, center: vec2<f32>, radius: f32) -> Vec<usize> {
+        let rect = Aabb2::point(center).extend_uniform(radius);
+        self.indices_in_rect(rect)
+            .filter(|&index| (self.cell_rect(index).center() - center).len() <= radius)
+            .filter(|&index| {
+                self.cells[index]
+                    .deposit
+                    .as_ref()
+                    .is_some_and(|deposit| deposit.ty == ty && deposit.amount > 0.0)
+            })
+            .collect()
+    }
+
+    /// Ore of type `ty` left within `radius` of `center`
+    pub fn deposit_amount(&self, ty: usize, center: vec2<f32>, radius: f32) -> f32 {
+        self.deposit_cells(ty, center, radius)
+            .into_iter()
+            .map(|index| self.cells[index].deposit.as_ref().unwrap().amount)
+            .sum()
+    }
+
+    /// Take up to `amount` of ore of type `ty` from around `center`, returning how much
+    /// there was
+    pub fn extract(&mut self, ty: usize, center: vec2<f32>, radius: f32, amount: f32) -> f32 {
+        let mut extracted = 0.0;
+        for index in self.deposit_cells(ty, center, radius) {
+            let deposit = self.cells[index].deposit.as_mut().unwrap();
+            let taken = deposit.amount.min(amount - extracted);
+            deposit.amount -= taken;
+            extracted += taken;
+            if extracted >= amount {
+                break;
+            }
+        }
+        extracted
+    }
+}
+
+/// Pseudo-random value in `0..1` for a lattice point
+fn lattice_value(seed: u64, x: i64, y: i64) -> f32 {
+    // splitmix64 finalizer over the combined coordinates
+    let mut z = seed
+        .wrapping_add((x as u64).wrapping_mul(0x9E3779B97F4A7C15))
+        .wrapping_add((y as u64).wrapping_mul(0xC2B2AE3D27D4EB4F));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^= z >> 31;
+    (z >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Smoothly interpolated value noise in `0..1` with features about 1 unit apart
+fn value_noise(seed: u64, pos: vec2<f32>) -> f32 {
+    let (x0, y0) = (pos.x.floor(), pos.y.floor());
+    let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+    let (tx, ty) = (smooth(pos.x - x0), smooth(pos.y - y0));
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    lerp(
+        lerp(
+            lattice_value(seed, x0, y0),
+            lattice_value(seed, x0 + 1, y0),
+            tx,
+        ),
+        lerp(
+            lattice_value(seed, x0, y0 + 1),
+            lattice_value(seed, x0 + 1, y0 + 1),
+            tx,
+        ),
+        ty,
+    )
+}
+
+/// Sum of `octaves` layers of value noise of halving size and weight, in `0..1`
+fn fractal_noise(seed: u64, pos: vec2<f32>, octaves: usize) -> f32 {
+    let mut total = 0.0;
+    let mut weight = 1.0;
+    let mut weights = 0.0;
+    for octave in 0..octaves.max(1) {
+        let scale = (1 << octave) as f32;
+        total += value_noise(seed.wrapping_add(octave as u64 * 1000), pos * scale) * weight;
+        weights += weight;
+        weight /= 2.0;
+    }
+    total / weights
+}
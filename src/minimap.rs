@@ -0,0 +1,113 @@
+use super::*;
+
+#[derive(Deserialize)]
+pub struct MinimapConfig {
+    /// Side of the minimap in `target_window_height` units
+    pub size: f32,
+    pub background_color: Rgba<f32>,
+    pub track_color: Rgba<f32>,
+    pub view_color: Rgba<f32>,
+    /// In `target_window_height` units
+    pub line_width: f32,
+    pub factory_radius: f32,
+    pub train_radius: f32,
+}
+
+impl Game {
+    /// Where the minimap is, in the bottom left corner of the UI camera
+    pub(crate) fn minimap_rect(&self) -> Aabb2<f32> {
+        let height = self.config.control.target_window_height;
+        let width = height * self.framebuffer_size.x / self.framebuffer_size.y;
+        let corner = -vec2(width, height) / 2.0 + vec2::splat(self.config.hud.margin);
+        Aabb2::from_corners(corner, corner + vec2::splat(self.config.minimap.size))
+    }
+
+    /// Minimap size of one world unit. The map is square like the minimap, so it is the same
+    /// both ways.
+    pub(crate) fn minimap_scale(&self) -> f32 {
+        self.minimap_rect().height() / self.world.map.bounds().height()
+    }
+
+    /// Camera showing the whole map on the minimap, for drawing in world coordinates
+    pub(crate) fn minimap_camera(&self) -> Camera2d {
+        let scale = self.minimap_scale();
+        Camera2d {
+            center: self.world.map.bounds().center() - self.minimap_rect().center() / scale,
+            rotation: Angle::ZERO,
+            fov: Camera2dFov::Vertical(self.config.control.target_window_height / scale),
+        }
+    }
+
+    fn world_to_minimap(&self, pos: vec2<f32>) -> vec2<f32> {
+        let rect = self.minimap_rect();
+        let bounds = self.world.map.bounds();
+        rect.min + (pos - bounds.min) / bounds.size() * rect.size()
+    }
+
+    pub(crate) fn minimap_to_world(&self, pos: vec2<f32>) -> vec2<f32> {
+        let rect = self.minimap_rect();
+        let bounds = self.world.map.bounds();
+        bounds.min + (pos - rect.min) / rect.size() * bounds.size()
+    }
+
+    /// World position under a screen position, if it is over the minimap
+    pub(crate) fn minimap_pick(&self, screen_pos: vec2<f64>) -> Option<vec2<f32>> {
+        let ui_pos = self
+            .ui_camera()
+            .screen_to_world(self.framebuffer_size, screen_pos.map(|x| x as f32));
+        self.minimap_rect()
+            .contains(ui_pos)
+            .then(|| self.minimap_to_world(ui_pos))
+    }
+
+    pub(crate) fn draw_minimap(&mut self, framebuffer: &mut ugli::Framebuffer) {
+        let camera = self.ui_camera();
+        self.geng.draw2d().draw2d(
+            framebuffer,
+            &camera,
+            &draw2d::Quad::new(self.minimap_rect(), self.config.minimap.background_color),
+        );
+        self.draw_minimap_tracks(framebuffer);
+        let config = &self.config.minimap;
+        for factory in &self.world.factories {
+            self.geng.draw2d().draw2d(
+                framebuffer,
+                &camera,
+                &draw2d::Ellipse::circle(
+                    self.world_to_minimap(factory.pos),
+                    config.factory_radius,
+                    self.factory_types[factory.ty].color,
+                ),
+            );
+        }
+        for train in &self.world.trains {
+            self.geng.draw2d().draw2d(
+                framebuffer,
+                &camera,
+                &draw2d::Ellipse::circle(
+                    self.world_to_minimap(self.world.tracks.point_pos(train.head)),
+                    config.train_radius,
+                    self.config.train.color,
+                ),
+            );
+        }
+        let view = self.camera.view_area(self.framebuffer_size).bounding_box();
+        let corners = [
+            view.min,
+            vec2(view.max.x, view.min.y),
+            view.max,
+            vec2(view.min.x, view.max.y),
+            view.min,
+        ];
+        self.geng.draw2d().draw2d(
+            framebuffer,
+            &camera,
+            &draw2d::Chain::new(
+                corners.map(|pos| self.world_to_minimap(pos)).to_vec(),
+                config.line_width,
+                config.view_color,
+                1,
+            ),
+        );
+    }
+}
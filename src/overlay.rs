@@ -0,0 +1,142 @@
+use super::*;
+
+#[derive(Deserialize)]
+pub struct OverlayConfig {
+    /// Track width of the busiest segment, in track widths
+    pub max_width: f32,
+    pub traffic_color: Rgba<f32>,
+    pub waiting_color: Rgba<f32>,
+}
+
+/// What is drawn over the tracks
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Overlay {
+    /// Trains that went through each segment
+    Traffic,
+    /// Time trains spent held back on each segment
+    Waiting,
+    /// Cargo carried through each segment, per resource
+    Cargo,
+}
+
+impl Overlay {
+    /// The one shown after this one when cycling, none after the last
+    pub fn next(overlay: Option<Self>) -> Option<Self> {
+        match overlay {
+            None => Some(Overlay::Traffic),
+            Some(Overlay::Traffic) => Some(Overlay::Waiting),
+            Some(Overlay::Waiting) => Some(Overlay::Cargo),
+            Some(Overlay::Cargo) => None,
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct SegmentTraffic {
+    pub trains: f32,
+    /// Seconds
+    pub waiting: f32,
+    pub cargo: HashMap<Id, f32>,
+}
+
+/// Usage of every segment since the start, keyed by [Tracks::segment_key]
+#[derive(Default)]
+pub struct Traffic {
+    pub segments: HashMap<(Id, Id), SegmentTraffic>,
+}
+
+impl Traffic {
+    fn segment(&mut self, a: Id, b: Id) -> &mut SegmentTraffic {
+        self.segments.entry(Tracks::segment_key(a, b)).or_default()
+    }
+
+    /// A train entered the segment
+    pub fn enter(&mut self, a: Id, b: Id, resource: Id, amount: f32) {
+        let segment = self.segment(a, b);
+        segment.trains += 1.0;
+        if amount > 0.0 {
+            *segment.cargo.entry(resource).or_default() += amount;
+        }
+    }
+
+    pub fn wait(&mut self, a: Id, b: Id, time: f32) {
+        self.segment(a, b).waiting += time;
+    }
+
+    /// Carry the history of a split segment over to both of its halves
+    pub fn split(&mut self, a: Id, b: Id, node: Id) {
+        if let Some(segment) = self.segments.remove(&Tracks::segment_key(a, b)) {
+            *self.segment(a, node) = segment.clone();
+            *self.segment(node, b) = segment;
+        }
+    }
+}
+
+impl Game {
+    pub(crate) fn draw_overlay(&self, framebuffer: &mut ugli::Framebuffer, overlay: Overlay) {
+        let config = &self.config.overlay;
+        let width = self.config.track.width * config.max_width;
+        let traffic = &self.world.traffic.segments;
+        // Thickness is relative to the busiest segment
+        let max = |value: fn(&SegmentTraffic) -> f32| {
+            traffic.values().map(value).fold(0.0, f32::max).max(1e-3)
+        };
+        let max_trains = max(|segment| segment.trains);
+        let max_waiting = max(|segment| segment.waiting);
+        let max_cargo = max(|segment| segment.cargo.values().copied().fold(0.0, f32::max));
+        for (key, curve) in &self.world.tracks.curves {
+            let Some(segment) = traffic.get(key) else {
+                continue;
+            };
+            let lines: Vec<(f32, Rgba<f32>)> = match overlay {
+                Overlay::Traffic => vec![(segment.trains / max_trains, config.traffic_color)],
+                Overlay::Waiting => vec![(segment.waiting / max_waiting, config.waiting_color)],
+                Overlay::Cargo => segment
+                    .cargo
+                    .iter()
+                    .map(|(resource, &amount)| {
+                        let color = self.world.resources.get(resource).unwrap().color;
+                        (amount / max_cargo, color)
+                    })
+                    // Widest first so that the others stay visible on top
+                    .sorted_by_key(|&(share, _)| -r32(share))
+                    .collect(),
+            };
+            for (share, color) in lines {
+                if share <= 0.0 {
+                    continue;
+                }
+                self.geng.draw2d().draw2d(
+                    framebuffer,
+                    &self.camera,
+                    &draw2d::Chain::new(curve.points().to_vec(), width * share, color, 4),
+                );
+            }
+        }
+    }
+
+    /// HUD line explaining the overlay
+    pub(crate) fn overlay_legend(&self, overlay: Overlay) -> String {
+        let traffic = self.world.traffic.segments.values();
+        match overlay {
+            Overlay::Traffic => format!(
+                "Traffic: busiest segment {:.0} trains",
+                traffic.map(|segment| segment.trains).fold(0.0, f32::max)
+            ),
+            Overlay::Waiting => format!(
+                "Waiting: worst segment {:.0}s",
+                traffic.map(|segment| segment.waiting).fold(0.0, f32::max)
+            ),
+            Overlay::Cargo => {
+                let resources = self
+                    .world
+                    .resources
+                    .iter()
+                    .sorted_by_key(|resource| resource.id)
+                    .map(|resource| resource.name.as_str())
+                    .join(", ");
+                format!("Cargo flow: {resources}")
+            }
+        }
+    }
+}
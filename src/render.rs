@@ -0,0 +1,339 @@
+use super::*;
+
+/// Level of detail thresholds, sizes on screen in `target_window_height` units
+#[derive(Deserialize)]
+pub struct LodConfig {
+    /// Labels and amounts smaller than this are not drawn
+    pub min_text_size: f32,
+    /// Trains thinner than this are drawn as dots
+    pub min_train_width: f32,
+    pub train_dot_radius: f32,
+}
+
+#[derive(ugli::Vertex, Debug, Copy, Clone, PartialEq)]
+pub struct TrackVertex {
+    pub a_pos: vec2<f32>,
+    pub a_color: Rgba<f32>,
+}
+
+/// Corner of the quad every train piece is drawn with,
+/// x going along the piece from 0 to 1 and y across it from -1 to 1
+#[derive(ugli::Vertex, Debug, Copy, Clone)]
+pub struct QuadVertex {
+    pub a_pos: vec2<f32>,
+}
+
+/// Straight piece of a train, drawn with round ends so that pieces join smoothly.
+/// A piece starting where it ends is a dot.
+#[derive(ugli::Vertex, Debug, Copy, Clone, PartialEq)]
+pub struct TrainInstance {
+    pub i_start: vec2<f32>,
+    pub i_end: vec2<f32>,
+    pub i_width: f32,
+    pub i_color: Rgba<f32>,
+}
+
+/// Triangles covering a polyline `width` wide, with mitered joints
+pub fn line_triangles(
+    points: &[vec2<f32>],
+    width: f32,
+    color: Rgba<f32>,
+    triangles: &mut Vec<TrackVertex>,
+) {
+    if points.len() < 2 {
+        return;
+    }
+    let normal = |a: vec2<f32>, b: vec2<f32>| (b - a).normalize_or_zero().rotate_90();
+    let offsets: Vec<vec2<f32>> = (0..points.len())
+        .map(|i| {
+            let before = normal(points[i.max(1) - 1], points[i.max(1)]);
+            let after = normal(
+                points[i.min(points.len() - 2)],
+                points[i.min(points.len() - 2) + 1],
+            );
+            let miter = (before + after).normalize_or_zero();
+            // Sharp turns would make the miter spike out, so it gets cut at twice the width
+            let scale = 1.0 / vec2::dot(miter, before).max(0.5);
+            miter * scale * width / 2.0
+        })
+        .collect();
+    let vertex = |a_pos| TrackVertex {
+        a_pos,
+        a_color: color,
+    };
+    for ((&a, &offset_a), (&b, &offset_b)) in points.iter().zip(&offsets).tuple_windows() {
+        triangles.extend([
+            vertex(a - offset_a),
+            vertex(a + offset_a),
+            vertex(b + offset_b),
+            vertex(a - offset_a),
+            vertex(b + offset_b),
+            vertex(b - offset_b),
+        ]);
+    }
+}
+
+/// All tracks as one list of triangles, bridge decks under the rails
+pub fn track_mesh(tracks: &Tracks, config: &TrackConfig) -> Vec<TrackVertex> {
+    let mut decks = Vec::new();
+    let mut rails = Vec::new();
+    for (&(a, b), curve) in &tracks.curves {
+        let color = match tracks.kind(a, b) {
+            SegmentKind::Track => config.color,
+            SegmentKind::Bridge => {
+                line_triangles(
+                    curve.points(),
+                    config.width * 2.0,
+                    config.bridge.color,
+                    &mut decks,
+                );
+                config.color
+            }
+            SegmentKind::Tunnel => config.tunnel.color,
+        };
+        line_triangles(curve.points(), config.width, color, &mut rails);
+    }
+    decks.extend(rails);
+    decks
+}
+
+/// Tracks on the minimap, all the same color and `width` world units wide
+pub fn minimap_track_mesh(tracks: &Tracks, width: f32, color: Rgba<f32>) -> Vec<TrackVertex> {
+    let mut triangles = Vec::new();
+    for curve in tracks.curves.values() {
+        line_triangles(curve.points(), width, color, &mut triangles);
+    }
+    triangles
+}
+
+/// Points along the track covered by the train, from the head back
+pub fn train_points(tracks: &Tracks, train: &Train) -> Vec<vec2<f32>> {
+    let head_length = tracks.segment_length(train.head.from, train.head.to);
+    let mut covered_length = head_length * train.head.ratio;
+    let mut points = tracks.segment_points(
+        train.head.from,
+        train.head.to,
+        train.head.ratio,
+        if head_length > 0.0 {
+            (train.head.ratio - train.length / head_length).max(0.0)
+        } else {
+            0.0
+        },
+    );
+    for (a, b) in train.tail_nodes.iter().copied().tuple_windows() {
+        if covered_length >= train.length {
+            break;
+        }
+        let segment_length = tracks.segment_length(b, a);
+        let uncovered = (train.length - covered_length) / segment_length;
+        points.extend(tracks.segment_points(b, a, 1.0, (1.0 - uncovered).max(0.0)));
+        covered_length += segment_length;
+    }
+    points.dedup();
+    points
+}
+
+/// Pieces to draw all trains with, or a single dot per train if `dot_radius` is given
+pub fn train_instances<'a>(
+    tracks: &Tracks,
+    trains: impl IntoIterator<Item = &'a Train>,
+    config: &TrainConfig,
+    dot_radius: Option<f32>,
+) -> Vec<TrainInstance> {
+    let mut instances = Vec::new();
+    for train in trains {
+        if let Some(radius) = dot_radius {
+            let pos = tracks.point_pos(train.head);
+            instances.push(TrainInstance {
+                i_start: pos,
+                i_end: pos,
+                i_width: radius * 2.0,
+                i_color: config.color,
+            });
+            continue;
+        }
+        let points = train_points(tracks, train);
+        instances.extend(points.into_iter().tuple_windows().map(|(i_start, i_end)| {
+            TrainInstance {
+                i_start,
+                i_end,
+                i_width: config.width,
+                i_color: config.color,
+            }
+        }));
+    }
+    instances
+}
+
+/// GPU side of track and train drawing
+pub struct Renderer {
+    track_program: ugli::Program,
+    train_program: ugli::Program,
+    /// Baked tracks and the [Tracks::revision] they were baked at
+    tracks: Option<(u64, ugli::VertexBuffer<TrackVertex>)>,
+    /// Same for the minimap
+    minimap_tracks: Option<(u64, ugli::VertexBuffer<TrackVertex>)>,
+    quad: ugli::VertexBuffer<QuadVertex>,
+    trains: ugli::VertexBuffer<TrainInstance>,
+}
+
+impl Renderer {
+    pub fn new(geng: &Geng) -> Self {
+        let compile = |source| geng.shader_lib().compile(source).unwrap();
+        let quad = [(0.0, -1.0), (1.0, -1.0), (1.0, 1.0), (0.0, 1.0)]
+            .map(|(x, y)| QuadVertex { a_pos: vec2(x, y) })
+            .to_vec();
+        Self {
+            track_program: compile(include_str!("../assets/shaders/track.glsl")),
+            train_program: compile(include_str!("../assets/shaders/train.glsl")),
+            tracks: None,
+            minimap_tracks: None,
+            quad: ugli::VertexBuffer::new_static(geng.ugli(), quad),
+            trains: ugli::VertexBuffer::new_dynamic(geng.ugli(), Vec::new()),
+        }
+    }
+
+    fn draw_tracks(
+        &mut self,
+        geng: &Geng,
+        framebuffer: &mut ugli::Framebuffer,
+        camera: &Camera2d,
+        tracks: &Tracks,
+        config: &TrackConfig,
+    ) {
+        let vertices = bake(geng, &mut self.tracks, tracks.revision, || {
+            track_mesh(tracks, config)
+        });
+        draw_triangles(&self.track_program, framebuffer, camera, vertices);
+    }
+
+    /// Draw the tracks as lines `width` world units wide, for a camera that shows the map
+    /// in the minimap
+    fn draw_minimap_tracks(
+        &mut self,
+        geng: &Geng,
+        framebuffer: &mut ugli::Framebuffer,
+        camera: &Camera2d,
+        tracks: &Tracks,
+        width: f32,
+        color: Rgba<f32>,
+    ) {
+        let vertices = bake(geng, &mut self.minimap_tracks, tracks.revision, || {
+            minimap_track_mesh(tracks, width, color)
+        });
+        draw_triangles(&self.track_program, framebuffer, camera, vertices);
+    }
+
+    fn draw_trains(
+        &mut self,
+        framebuffer: &mut ugli::Framebuffer,
+        camera: &Camera2d,
+        instances: Vec<TrainInstance>,
+    ) {
+        *self.trains = instances;
+        let framebuffer_size = framebuffer.size().map(|x| x as f32);
+        ugli::draw(
+            framebuffer,
+            &self.train_program,
+            ugli::DrawMode::TriangleFan,
+            ugli::instanced(&self.quad, &self.trains),
+            camera.uniforms(framebuffer_size),
+            ugli::DrawParameters {
+                blend_mode: Some(ugli::BlendMode::straight_alpha()),
+                ..default()
+            },
+        );
+    }
+}
+
+/// Buffer in `baked`, baked again from `mesh` if it is from before `revision`
+fn bake<'a>(
+    geng: &Geng,
+    baked: &'a mut Option<(u64, ugli::VertexBuffer<TrackVertex>)>,
+    revision: u64,
+    mesh: impl FnOnce() -> Vec<TrackVertex>,
+) -> &'a ugli::VertexBuffer<TrackVertex> {
+    if baked
+        .as_ref()
+        .is_none_or(|&(baked_at, _)| baked_at != revision)
+    {
+        *baked = Some((
+            revision,
+            ugli::VertexBuffer::new_static(geng.ugli(), mesh()),
+        ));
+    }
+    &baked.as_ref().unwrap().1
+}
+
+fn draw_triangles(
+    program: &ugli::Program,
+    framebuffer: &mut ugli::Framebuffer,
+    camera: &Camera2d,
+    vertices: &ugli::VertexBuffer<TrackVertex>,
+) {
+    let framebuffer_size = framebuffer.size().map(|x| x as f32);
+    ugli::draw(
+        framebuffer,
+        program,
+        ugli::DrawMode::Triangles,
+        vertices,
+        camera.uniforms(framebuffer_size),
+        ugli::DrawParameters {
+            blend_mode: Some(ugli::BlendMode::straight_alpha()),
+            ..default()
+        },
+    );
+}
+
+impl Game {
+    /// Whether text `size` world units tall is big enough on screen to be drawn
+    pub(crate) fn text_visible(&self, size: f32) -> bool {
+        size >= self.ui_to_world_distance(self.config.lod.min_text_size)
+    }
+
+    /// Whether trains are too thin on screen to be drawn along the track
+    pub(crate) fn trains_as_dots(&self) -> bool {
+        self.config.train.width < self.ui_to_world_distance(self.config.lod.min_train_width)
+    }
+
+    pub(crate) fn draw_tracks(&mut self, framebuffer: &mut ugli::Framebuffer) {
+        self.renderer.draw_tracks(
+            &self.geng,
+            framebuffer,
+            &self.camera,
+            &self.world.tracks,
+            &self.config.track,
+        );
+    }
+
+    pub(crate) fn draw_trains(&mut self, framebuffer: &mut ugli::Framebuffer) {
+        let dot_radius = self
+            .trains_as_dots()
+            .then(|| self.ui_to_world_distance(self.config.lod.train_dot_radius));
+        let instances = train_instances(
+            &self.world.tracks,
+            &self.world.trains,
+            &self.config.train,
+            dot_radius,
+        );
+        self.renderer
+            .draw_trains(framebuffer, &self.camera, instances);
+    }
+
+    /// Draw the tracks on the minimap from a buffer baked once per [Tracks::revision]
+    pub(crate) fn draw_minimap_tracks(&mut self, framebuffer: &mut ugli::Framebuffer) {
+        let camera = self.minimap_camera();
+        let width = self.config.minimap.line_width / self.minimap_scale();
+        self.renderer.draw_minimap_tracks(
+            &self.geng,
+            framebuffer,
+            &camera,
+            &self.world.tracks,
+            width,
+            self.config.minimap.track_color,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,90 @@
+//! Vertex generation checked without a window
+
+use super::*;
+use crate::scenario::tests::{load, load_config, run, IRON_AGE};
+
+#[test]
+fn straight_track() {
+    let config = &load_config().track;
+    let mut id_gen = IdGen::new();
+    let mut tracks = Tracks::new(config.index_cell_size);
+    let a = tracks.add_node(TrackNode::new(&mut id_gen, vec2(0.0, 0.0)));
+    let b = tracks.add_node(TrackNode::new(&mut id_gen, vec2(20.0, 0.0)));
+    tracks.add_connection(a, b);
+
+    let points = tracks.curves.values().next().unwrap().points().len();
+    let mesh = track_mesh(&tracks, config);
+    assert_eq!(mesh.len(), (points - 1) * 6);
+    for vertex in &mesh {
+        assert_eq!(vertex.a_color, config.color);
+        assert!((vertex.a_pos.y.abs() - config.width / 2.0).abs() < 1e-3);
+        assert!((-1e-3..=20.0 + 1e-3).contains(&vertex.a_pos.x));
+    }
+}
+
+#[test]
+fn minimap_track() {
+    let config = load_config();
+    let mut id_gen = IdGen::new();
+    let mut tracks = Tracks::new(config.track.index_cell_size);
+    let a = tracks.add_node(TrackNode::new(&mut id_gen, vec2(0.0, 0.0)));
+    let b = tracks.add_node(TrackNode::new(&mut id_gen, vec2(20.0, 0.0)));
+    tracks.add_connection(a, b);
+    tracks.set_kind(a, b, SegmentKind::Bridge);
+
+    // No bridge decks, just one line of the minimap color
+    let points = tracks.curves.values().next().unwrap().points().len();
+    let mesh = minimap_track_mesh(&tracks, 2.0, config.minimap.track_color);
+    assert_eq!(mesh.len(), (points - 1) * 6);
+    for vertex in &mesh {
+        assert_eq!(vertex.a_color, config.minimap.track_color);
+        assert!((vertex.a_pos.y.abs() - 1.0).abs() < 1e-3);
+    }
+}
+
+#[test]
+fn revision_follows_topology() {
+    let config = load_config();
+    let mut id_gen = IdGen::new();
+    let mut tracks = Tracks::new(config.track.index_cell_size);
+    let a = tracks.add_node(TrackNode::new(&mut id_gen, vec2(0.0, 0.0)));
+    let b = tracks.add_node(TrackNode::new(&mut id_gen, vec2(20.0, 0.0)));
+    let before = tracks.revision;
+    tracks.add_connection(a, b);
+    let connected = tracks.revision;
+    assert_ne!(before, connected);
+    // Connecting twice changes nothing
+    tracks.add_connection(a, b);
+    assert_eq!(tracks.revision, connected);
+    tracks.remove_connection(a, b);
+    assert_ne!(tracks.revision, connected);
+}
+
+#[test]
+fn train_pieces() {
+    let mut world = load(IRON_AGE);
+    run(&mut world, 500);
+    assert!(!world.trains.is_empty());
+    let config = &world.config.train;
+    for train in &world.trains {
+        let pieces = train_instances(&world.tracks, [train], config, None);
+        let length: f32 = pieces
+            .iter()
+            .map(|piece| (piece.i_end - piece.i_start).len())
+            .sum();
+        assert!(length > 0.0, "Train {} is not drawn", train.id.0);
+        assert!(
+            length <= train.length + 1e-3,
+            "Train {} is drawn {length} long",
+            train.id.0
+        );
+        assert_eq!(pieces[0].i_start, world.tracks.point_pos(train.head));
+    }
+
+    let dots = train_instances(&world.tracks, &world.trains, config, Some(1.0));
+    assert_eq!(dots.len(), world.trains.len());
+    for dot in dots {
+        assert_eq!(dot.i_start, dot.i_end);
+        assert_eq!(dot.i_width, 2.0);
+    }
+}
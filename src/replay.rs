@@ -0,0 +1,232 @@
+use super::*;
+
+/// Player action that changes the world, the unit that replays are made of
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum Command {
+    /// Lone node, no longer made by the game but kept so that older replays still play
+    AddNode {
+        pos: vec2<f32>,
+    },
+    BuildTrack {
+        start: TrackEnd,
+        end: TrackEnd,
+        handles: [vec2<f32>; 2],
+    },
+    SpawnFactory {
+        pos: vec2<f32>,
+        /// In degrees
+        angle: f32,
+        ty: usize,
+    },
+    SpawnTrain {
+        wagon: usize,
+        schedule: Vec<String>,
+    },
+    PlaceStation {
+        site: StationSite,
+    },
+    UpgradeLoader {
+        node: Id,
+    },
+    Delete {
+        selection: Selection,
+    },
+    Move {
+        selection: Selection,
+        offset: vec2<f32>,
+    },
+    Copy {
+        selection: Selection,
+        offset: vec2<f32>,
+    },
+    Paste {
+        blueprint: Blueprint,
+        placement: Placement,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct RecordedCommand {
+    /// Simulation steps taken before the command
+    pub tick: u64,
+    pub command: Command,
+}
+
+/// How a session started and everything the player did in it
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Replay {
+    pub seed: u64,
+    pub scenario: Option<PathBuf>,
+    /// Length of a simulation step in seconds
+    pub tick: f32,
+    pub commands: Vec<RecordedCommand>,
+}
+
+impl Replay {
+    pub fn new(world: &World, scenario: Option<PathBuf>, tick: f32) -> Self {
+        Self {
+            seed: world.seed,
+            scenario,
+            tick,
+            commands: Vec::new(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self).unwrap())
+    }
+}
+
+/// Replay being recorded to a file. Rewriting the file after every command gets slow as it
+/// grows, so it is saved at most every [SimulationConfig::replay_save_interval] seconds and
+/// once more when the game closes.
+pub(crate) struct Recording {
+    path: PathBuf,
+    replay: Replay,
+    /// Seconds until the next save, `None` while there is nothing new to save
+    save_in: Option<f32>,
+}
+
+impl Recording {
+    pub fn new(path: PathBuf, replay: Replay) -> Self {
+        Self {
+            path,
+            replay,
+            save_in: Some(0.0),
+        }
+    }
+
+    pub fn record(&mut self, recorded: RecordedCommand, save_interval: f32) {
+        self.replay.commands.push(recorded);
+        self.save_in.get_or_insert(save_interval);
+    }
+
+    /// Save once the interval since the first unsaved command has passed
+    pub fn update(&mut self, delta_time: f32) -> std::io::Result<()> {
+        match &mut self.save_in {
+            Some(time_left) if *time_left > delta_time => {
+                *time_left -= delta_time;
+                Ok(())
+            }
+            Some(_) => self.save(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn save(&mut self) -> std::io::Result<()> {
+        self.save_in = None;
+        self.replay.save(&self.path)
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        if self.save_in.is_some() {
+            if let Err(error) = self.save() {
+                eprintln!("Failed to save the replay: {error}");
+            }
+        }
+    }
+}
+
+/// Apply the `pending` commands that are due before the next step of `world`
+pub(crate) fn execute_due(world: &mut World, pending: &mut VecDeque<RecordedCommand>) {
+    while let Some(recorded) = pending.pop_front() {
+        if recorded.tick > world.tick {
+            pending.push_front(recorded);
+            break;
+        }
+        // Commands that failed when recorded fail the same way again
+        let _ = world.execute(&recorded.command);
+    }
+}
+
+impl World {
+    /// Apply a player command, returning the node or train it created
+    pub fn execute(&mut self, command: &Command) -> Result<Option<Id>, String> {
+        self.check_command(command)?;
+        match *command {
+            Command::AddNode { pos } => self.place_node(pos).map(Some),
+            Command::BuildTrack {
+                start,
+                end,
+                handles,
+            } => self.build_track(start, end, handles).map(Some),
+            Command::SpawnFactory { pos, angle, ty } => self
+                .spawn_factory(pos, Angle::from_degrees(angle), ty)
+                .map(|()| None),
+            Command::SpawnTrain {
+                wagon,
+                ref schedule,
+            } => self.spawn_train(wagon, schedule.clone()).map(Some),
+            Command::PlaceStation { site } => self.place_station(site).map(Some),
+            Command::UpgradeLoader { node } => self.upgrade_loader(node).map(|()| None),
+            Command::Delete { ref selection } => self.delete(selection).map(|()| None),
+            Command::Move {
+                ref selection,
+                offset,
+            } => self.move_selection(selection, offset).map(|()| None),
+            Command::Copy {
+                ref selection,
+                offset,
+            } => self.copy_selection(selection, offset).map(|()| None),
+            Command::Paste {
+                ref blueprint,
+                placement,
+            } => self.paste(blueprint, placement).map(|()| None),
+        }
+    }
+
+    /// Make sure every id and index in `command` points at something, as replays can hold
+    /// anything. Selections skip whatever is gone and blueprints check themselves.
+    fn check_command(&self, command: &Command) -> Result<(), String> {
+        let node = |id: Id| match self.tracks.nodes.get(&id) {
+            Some(_) => Ok(()),
+            None => Err(format!("No node {}", id.0)),
+        };
+        let index = |what: &str, index: usize, count: usize| {
+            if index < count {
+                Ok(())
+            } else {
+                Err(format!("{what} {index} out of range, there are {count}"))
+            }
+        };
+        match *command {
+            Command::BuildTrack { start, end, .. } => {
+                for end in [start, end] {
+                    if let TrackEnd::Node(id) = end {
+                        node(id)?;
+                    }
+                }
+            }
+            Command::SpawnFactory { ty, .. } => {
+                index("Factory type", ty, self.factory_types.factory.len())?;
+            }
+            Command::SpawnTrain { wagon, .. } => {
+                index("Wagon type", wagon, self.config.train.wagon.len())?;
+            }
+            Command::PlaceStation {
+                site: StationSite::Node(id),
+            }
+            | Command::UpgradeLoader { node: id } => node(id)?,
+            Command::PlaceStation {
+                site: StationSite::Track(point),
+            } => {
+                let on_track = self
+                    .tracks
+                    .nodes
+                    .get(&point.from)
+                    .is_some_and(|from| from.connections.contains(&point.to));
+                if !on_track || !(0.0..=1.0).contains(&point.ratio) {
+                    return Err(format!("No track point {point:?}"));
+                }
+            }
+            Command::AddNode { .. }
+            | Command::Delete { .. }
+            | Command::Move { .. }
+            | Command::Copy { .. }
+            | Command::Paste { .. } => {}
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+use super::*;
+
+/// What other trains are doing, used to make routes avoid busy parts of the network
+#[derive(Default)]
+pub struct Congestion {
+    /// Trains on each segment, keyed by [Tracks::segment_key]
+    occupied_segments: HashMap<(Id, Id), i32>,
+    /// Trains heading to or standing at each node
+    waiting_trains: HashMap<Id, i32>,
+}
+
+impl Congestion {
+    pub fn new(trains: &Collection<Train>) -> Self {
+        let mut congestion = Self::default();
+        for train in trains {
+            congestion.record(train, 1);
+        }
+        congestion
+    }
+
+    /// Add (`delta = 1`) or remove (`delta = -1`) a train's contribution
+    pub fn record(&mut self, train: &Train, delta: i32) {
+        let head = (train.head.from != train.head.to).then_some((train.head.from, train.head.to));
+        let tail = train.tail_nodes.iter().copied().tuple_windows();
+        for (a, b) in head.into_iter().chain(tail) {
+            *self
+                .occupied_segments
+                .entry(Tracks::segment_key(a, b))
+                .or_default() += delta;
+        }
+        if let Some(target) = train.target {
+            *self.waiting_trains.entry(target.node()).or_default() += delta;
+        }
+    }
+
+    pub fn occupied(&self, a: Id, b: Id) -> i32 {
+        self.occupied_segments
+            .get(&Tracks::segment_key(a, b))
+            .copied()
+            .unwrap_or(0)
+            .max(0)
+    }
+
+    pub fn waiting(&self, node: Id) -> i32 {
+        self.waiting_trains.get(&node).copied().unwrap_or(0).max(0)
+    }
+}
+
+impl Tracks {
+    /// How fast a train with the given `top_speed` goes between `a` and `b`
+    pub fn speed(&self, a: Id, b: Id, top_speed: f32, config: &TrackConfig) -> f32 {
+        top_speed.min(config.speed_limit(self.kind(a, b)))
+    }
+
+    /// Cheapest path from `from` to `to` and its cost, where cost is the time to drive it
+    /// at `top_speed`, measured as the track length covered in that time, plus
+    /// [RoutingConfig] penalties. `came_from` is the node the train is leaving, so turning
+    /// back towards it counts as a reversal.
+    ///
+    /// Speed limits and penalties only ever add to the length, so the cached distances to
+    /// `to` are an admissible heuristic and the search mostly follows the plain shortest
+    /// path.
+    pub fn route(
+        &self,
+        from: Id,
+        came_from: Option<Id>,
+        to: Id,
+        congestion: &Congestion,
+        top_speed: f32,
+        track: &TrackConfig,
+    ) -> Option<(Vec<Id>, f32)> {
+        let config = &track.routing;
+        let tree = self.route_tree(to);
+        if from != to && !tree.next.contains_key(&from) {
+            return None;
+        }
+        let (path, cost) = pathfinding::directed::astar::astar(
+            &from,
+            |&v| {
+                let node = self.nodes.get(&v).unwrap();
+                // Sorted so that ties between equally good paths always go the same way
+                node.connections
+                    .iter()
+                    .sorted()
+                    .map(|&u| {
+                        let mut cost = self.segment_length(v, u) * top_speed
+                            / self.speed(v, u, top_speed, track);
+                        cost += config.occupied_segment_penalty * congestion.occupied(v, u) as f32;
+                        if u != to {
+                            cost += config.waiting_train_penalty * congestion.waiting(u) as f32;
+                        }
+                        if v == from && Some(u) == came_from {
+                            cost += config.reversal_penalty;
+                        }
+                        (u, r32(cost))
+                    })
+                    .collect::<Vec<_>>()
+            },
+            |v| tree.next.get(v).map_or(r32(0.0), |&(_, distance)| distance),
+            |&v| v == to,
+        )?;
+        Some((path, cost.raw()))
+    }
+}
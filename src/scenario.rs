@@ -0,0 +1,241 @@
+use super::*;
+
+#[cfg(test)]
+pub(crate) mod tests;
+
+/// Scenario points closer than this to an existing track node are joined to it
+const JOIN_DISTANCE: f32 = 1.0;
+
+#[derive(Deserialize)]
+pub struct ScenarioDeposit {
+    pub resource: String,
+    pub pos: vec2<f32>,
+    pub radius: f32,
+    /// Per map cell
+    pub amount: f32,
+}
+
+#[derive(Deserialize)]
+pub struct ScenarioFactory {
+    /// Name of the factory type
+    pub r#type: String,
+    pub pos: vec2<f32>,
+    /// In degrees, rotates the ios around the factory
+    #[serde(default)]
+    pub angle: f32,
+}
+
+/// Nodes connected one after another
+#[derive(Deserialize)]
+pub struct ScenarioTrack {
+    pub points: Vec<vec2<f32>>,
+}
+
+#[derive(Deserialize)]
+pub struct ScenarioStation {
+    pub name: String,
+    /// Put on the closest track
+    pub pos: vec2<f32>,
+}
+
+#[derive(Deserialize)]
+pub struct ScenarioTrain {
+    pub resource: String,
+    /// Starts at the closest track node
+    pub pos: vec2<f32>,
+    /// Name of the wagon type, the first one if missing
+    pub wagon: Option<String>,
+    #[serde(default)]
+    pub schedule: Vec<String>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum Goal {
+    /// Unload `amount` of `resource` into factories of type `to`
+    Deliver {
+        resource: String,
+        to: String,
+        amount: f32,
+    },
+    /// Have at least `amount` money
+    Balance { amount: f32 },
+}
+
+impl Goal {
+    pub fn describe(&self) -> String {
+        match self {
+            Goal::Deliver {
+                resource,
+                to,
+                amount,
+            } => format!("Deliver {amount} {resource} to {to}"),
+            Goal::Balance { amount } => format!("Have ${amount}"),
+        }
+    }
+
+    pub fn target(&self) -> f32 {
+        match *self {
+            Goal::Deliver { amount, .. } | Goal::Balance { amount } => amount,
+        }
+    }
+}
+
+/// Starting state of the world and what the player has to achieve
+#[derive(Deserialize)]
+pub struct Scenario {
+    pub name: String,
+    /// Map generator seed, overridden by the command line
+    pub seed: Option<u64>,
+    /// Skip water, mountains and forests
+    #[serde(default)]
+    pub flat: bool,
+    /// Starting money instead of [EconomyConfig::starting_balance]
+    pub budget: Option<f32>,
+    /// Seconds to complete all goals in
+    pub time_limit: Option<f32>,
+    #[serde(default)]
+    pub deposit: Vec<ScenarioDeposit>,
+    #[serde(default)]
+    pub factory: Vec<ScenarioFactory>,
+    #[serde(default)]
+    pub track: Vec<ScenarioTrack>,
+    #[serde(default)]
+    pub station: Vec<ScenarioStation>,
+    #[serde(default)]
+    pub train: Vec<ScenarioTrain>,
+    #[serde(default)]
+    pub goal: Vec<Goal>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Victory,
+    Defeat,
+}
+
+impl World {
+    /// Build everything the scenario starts with, for free and regardless of terrain
+    pub(crate) fn load_scenario(&mut self, scenario: &Scenario) {
+        if scenario.flat {
+            self.map.flatten();
+        }
+        if let Some(budget) = scenario.budget {
+            self.wallet = Wallet::new(budget);
+        }
+
+        for deposit in &scenario.deposit {
+            let ty = self.deposit_type(&deposit.resource).unwrap_or_else(|| {
+                panic!("Unknown deposit resource {:?}", deposit.resource);
+            });
+            self.map
+                .add_deposit(ty, deposit.pos, deposit.radius, deposit.amount);
+        }
+
+        for factory in &scenario.factory {
+            let ty = self
+                .factory_types
+                .factory
+                .iter()
+                .position(|ty| ty.name == factory.r#type)
+                .unwrap_or_else(|| panic!("Unknown factory type {:?}", factory.r#type));
+            self.build_factory(factory.pos, Angle::from_degrees(factory.angle), ty);
+        }
+
+        for track in &scenario.track {
+            let nodes: Vec<Id> = track
+                .points
+                .iter()
+                .map(|&pos| {
+                    self.tracks
+                        .nearest_node(pos, JOIN_DISTANCE)
+                        .unwrap_or_else(|| {
+                            self.tracks.add_node(TrackNode::new(&mut self.id_gen, pos))
+                        })
+                })
+                .collect();
+            for (a, b) in nodes.into_iter().tuple_windows() {
+                let a_pos = self.tracks.nodes.get(&a).unwrap().pos;
+                let b_pos = self.tracks.nodes.get(&b).unwrap().pos;
+                let start_dir = self.tracks.continuation(a, b_pos);
+                let handles = curve_handles(a_pos, start_dir, b_pos, None);
+                let kind = self
+                    .plan_track(a_pos, b_pos, handles)
+                    .map_or(SegmentKind::Track, |plan| plan.kind);
+                self.tracks.add_curve(a, b, handles);
+                self.tracks.set_kind(a, b, kind);
+            }
+        }
+
+        for station in &scenario.station {
+            let node = match self.tracks.nearest_node(station.pos, JOIN_DISTANCE) {
+                Some(node) => node,
+                None => {
+                    let point = self
+                        .tracks
+                        .nearest_segment(station.pos, f32::INFINITY)
+                        .unwrap_or_else(|| panic!("No track for station {:?}", station.name));
+                    self.split_segment(point)
+                }
+            };
+            self.build_station(node, station.name.clone());
+        }
+
+        for train in &scenario.train {
+            let resource = self.resource_id(&train.resource);
+            let node = self
+                .tracks
+                .nearest_node(train.pos, f32::INFINITY)
+                .expect("No track for a train");
+            let wagon = train.wagon.as_ref().map_or(0, |name| {
+                self.config
+                    .train
+                    .wagon
+                    .iter()
+                    .position(|wagon| &wagon.name == name)
+                    .unwrap_or_else(|| panic!("Unknown wagon type {name:?}"))
+            });
+            self.build_train(resource, node, wagon, train.schedule.clone());
+        }
+
+        self.scenario_name = Some(scenario.name.clone());
+        self.goals = scenario.goal.clone();
+        self.time_limit = scenario.time_limit;
+    }
+
+    /// How far along a goal is, in the units of [Goal::target]
+    pub(crate) fn goal_progress(&self, goal: &Goal) -> f32 {
+        match goal {
+            Goal::Deliver { resource, to, .. } => self
+                .factories
+                .iter()
+                .filter(|factory| &self.factory_types[factory.ty].name == to)
+                .flat_map(|factory| &factory.io)
+                .filter(|io| {
+                    io.ty == IoType::Input
+                        && &self.resources.get(&io.resource).unwrap().name == resource
+                })
+                .map(|io| io.delivered)
+                .sum(),
+            Goal::Balance { .. } => self.wallet.balance(),
+        }
+    }
+
+    /// Victory once every goal is met, defeat when time runs out first
+    pub(crate) fn check_outcome(&self) -> Option<Outcome> {
+        if self.goals.is_empty() {
+            return None;
+        }
+        if self
+            .goals
+            .iter()
+            .all(|goal| self.goal_progress(goal) >= goal.target())
+        {
+            return Some(Outcome::Victory);
+        }
+        if self.time_limit.is_some_and(|limit| self.time >= limit) {
+            return Some(Outcome::Defeat);
+        }
+        None
+    }
+}
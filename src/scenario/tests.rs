@@ -0,0 +1,672 @@
+//! Headless runs of scenario files, compared against snapshots in `tests/snapshots`.
+//! Run with `UPDATE_SNAPSHOTS=1` to accept changed output, then review and commit the diff.
+//! The loading helpers are shared with the other tests.
+
+use std::fmt::Write;
+
+use super::*;
+
+/// Seconds per simulation step
+const TICK: f32 = 0.05;
+
+pub(crate) const IRON_AGE: &str = "assets/scenarios/iron_age.toml";
+
+pub(crate) fn load_config() -> Config {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    futures::executor::block_on(file::load_detect(root.join("assets/config.toml"))).unwrap()
+}
+
+pub(crate) fn load(scenario: &str) -> World {
+    load_seeded(scenario, None)
+}
+
+fn load_seeded(scenario: &str, seed: Option<u64>) -> World {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    futures::executor::block_on(World::load(
+        &root.join("assets"),
+        seed,
+        Some(&root.join(scenario)),
+    ))
+}
+
+pub(crate) fn run(world: &mut World, ticks: u64) {
+    play(world, &mut VecDeque::new(), ticks);
+}
+
+/// Step the world until `ticks` steps in, applying the `pending` commands on the way and
+/// checking trains and factories after every step
+fn play(world: &mut World, pending: &mut VecDeque<RecordedCommand>, ticks: u64) {
+    while world.tick < ticks {
+        execute_due(world, pending);
+        world.update(TICK);
+        check_trains(world);
+        check_factories(world);
+    }
+}
+
+fn check_trains(world: &World) {
+    for train in &world.trains {
+        let id = train.id.0;
+        assert!(
+            (0.0..=1.0).contains(&train.head.ratio),
+            "Train {id} is off its segment: {:?}",
+            train.head
+        );
+        assert!(
+            train.amount >= 0.0 && train.amount <= world.config.train.capacity + 1e-3,
+            "Train {id} carries {}",
+            train.amount
+        );
+        if let Some(&first) = train.tail_nodes.front() {
+            assert_eq!(first, train.head.from, "Train {id} tail is detached");
+        }
+        let lengths: Vec<f32> = train
+            .tail_nodes
+            .iter()
+            .copied()
+            .tuple_windows()
+            .map(|(a, b)| {
+                let node = world.tracks.nodes.get(&a).unwrap();
+                assert!(
+                    node.connections.contains(&b),
+                    "Train {id} tail jumps from {a:?} to {b:?}"
+                );
+                world.tracks.segment_length(a, b)
+            })
+            .collect();
+        // Only the last segment of the tail may reach past the end of the train
+        let kept: f32 = lengths.iter().rev().skip(1).sum();
+        assert!(
+            kept <= train.length + 1e-3,
+            "Train {id} tail is {kept} long, more than its length {}",
+            train.length
+        );
+        if let Some(path) = &train.path_from_target {
+            let ahead = std::iter::once(train.head.to).chain(path.iter().rev().copied());
+            for (a, b) in ahead.tuple_windows() {
+                let node = world.tracks.nodes.get(&a).unwrap();
+                assert!(
+                    a == b || node.connections.contains(&b),
+                    "Train {id} path jumps from {a:?} to {b:?}"
+                );
+            }
+        }
+    }
+}
+
+fn check_factories(world: &World) {
+    for factory in &world.factories {
+        for io in &factory.io {
+            if let Some(amount) = io.amount {
+                assert!(
+                    amount.is_finite() && amount >= 0.0,
+                    "Factory {} buffer holds {amount}",
+                    factory.id.0
+                );
+            }
+        }
+    }
+}
+
+fn delivered(world: &World, resource: &str, to: &str) -> f32 {
+    world.goal_progress(&Goal::Deliver {
+        resource: resource.to_owned(),
+        to: to.to_owned(),
+        amount: 0.0,
+    })
+}
+
+/// World state as text, ordered by id and rounded, so it only changes with the simulation
+fn snapshot(world: &World) -> String {
+    let mut out = String::new();
+    writeln!(out, "time {:.2}", world.time).unwrap();
+    writeln!(out, "balance {:.2}", world.wallet.balance()).unwrap();
+    writeln!(out, "outcome {:?}", world.outcome).unwrap();
+    for factory in world.factories.iter().sorted_by_key(|factory| factory.id) {
+        let name = &world.factory_types[factory.ty].name;
+        writeln!(out, "factory {} {name}", factory.id.0).unwrap();
+        for io in &factory.io {
+            let resource = &world.resources.get(&io.resource).unwrap().name;
+            let amount = io
+                .amount
+                .map_or("-".to_owned(), |amount| format!("{amount:.2}"));
+            writeln!(
+                out,
+                "  {:?} {resource}: buffer {amount}, delivered {:.2}",
+                io.ty, io.delivered
+            )
+            .unwrap();
+        }
+    }
+    for train in world.trains.iter().sorted_by_key(|train| train.id) {
+        let resource = &world.resources.get(&train.resource).unwrap().name;
+        let pos = world.tracks.point_pos(train.head);
+        let target = train
+            .target
+            .map_or("none".to_owned(), |target| world.target_name(target));
+        writeln!(
+            out,
+            "train {} {:.2} {resource} at ({:.2}, {:.2}), tail {}, target {target}",
+            train.id.0,
+            train.amount,
+            pos.x,
+            pos.y,
+            train.tail_nodes.len()
+        )
+        .unwrap();
+    }
+    for station in world.stations.iter().sorted_by_key(|station| station.id) {
+        let stats = world.station_stats.get(&station.node);
+        writeln!(
+            out,
+            "station {} {}: loaded {:.2}, unloaded {:.2}, visits {}",
+            station.id.0,
+            station.name,
+            stats.map_or(0.0, |stats| stats.loaded),
+            stats.map_or(0.0, |stats| stats.unloaded),
+            stats.map_or(0, |stats| stats.visits)
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Compare against the checked in snapshot, or write it when `UPDATE_SNAPSHOTS` is set.
+/// A missing snapshot fails like a changed one, so that it can't go unnoticed.
+fn assert_snapshot(name: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("snapshots")
+        .join(format!("{name}.snap"));
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|error| {
+        panic!(
+            "Can't read {}: {error}, run with UPDATE_SNAPSHOTS=1 to write it",
+            path.display()
+        )
+    });
+    assert_eq!(
+        actual,
+        expected,
+        "{} is out of date, rerun with UPDATE_SNAPSHOTS=1 if the change is intended",
+        path.display()
+    );
+}
+
+#[test]
+fn iron_age() {
+    let mut world = load(IRON_AGE);
+    // The first plates take about 5000 steps, the ore train being the bottleneck
+    run(&mut world, 10000);
+    let plates = delivered(&world, "Iron Plate", "The Hole");
+    assert!(plates > 0.0);
+    let statistics = &world.statistics;
+    let plate = world
+        .resources
+        .iter()
+        .find(|resource| resource.name == "Iron Plate")
+        .unwrap()
+        .id;
+    let sampled: f32 = statistics
+        .samples
+        .iter()
+        .chain([&statistics.current])
+        .filter_map(|sample| sample.delivered.get(&plate))
+        .sum();
+    assert!((sampled - plates).abs() < 1e-2, "{sampled} != {plates}");
+    assert_snapshot("iron_age", &snapshot(&world));
+}
+
+#[test]
+fn queued_stations() {
+    let mut world = load("tests/scenarios/stations.toml");
+    run(&mut world, 2400);
+    for station in &world.stations {
+        let visits = world
+            .station_stats
+            .get(&station.node)
+            .map_or(0, |stats| stats.visits);
+        assert!(visits > 0, "{} was never visited", station.name);
+    }
+    assert!(delivered(&world, "Iron Ore", "Smelting Iron Ore") > 0.0);
+    assert_snapshot("queued_stations", &snapshot(&world));
+}
+
+#[test]
+fn station_ahead_of_train() {
+    let mut world = load(IRON_AGE);
+    // Wait for a train in the middle of a segment, with room in front of it
+    let head = loop {
+        assert!(world.tick < 10000, "No train ever got going");
+        let tick = world.tick + 1;
+        run(&mut world, tick);
+        let moving = world.trains.iter().find(|train| {
+            let head = train.head;
+            let length = world.tracks.segment_length(head.from, head.to);
+            train.path_from_target.is_some()
+                && head.from != head.to
+                && head.ratio > 0.0
+                && (1.0 - head.ratio) * length > 4.0
+        });
+        if let Some(train) = moving {
+            break train.head;
+        }
+    };
+    let length = world.tracks.segment_length(head.from, head.to);
+    let site = TrackPoint {
+        ratio: head.ratio + 2.0 / length,
+        ..head
+    };
+    let node = world.place_station(StationSite::Track(site)).unwrap();
+    let tick = world.tick + 1000;
+    run(&mut world, tick);
+    let visits = world
+        .station_stats
+        .get(&node)
+        .map_or(0, |stats| stats.visits);
+    assert_eq!(visits, 0, "Trains stopped at a station nobody is headed to");
+}
+
+#[test]
+fn lone_nodes() {
+    // Scenarios are flat, a generated map has water and mountains
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut world = futures::executor::block_on(World::load(&root.join("assets"), Some(1), None));
+    let nodes = world.tracks.nodes.len();
+    let spots = (-50..50).flat_map(|x| (-50..50).map(move |y| vec2(x as f32, y as f32) * 4.0));
+    let unbuildable = spots
+        .clone()
+        .find(|&pos| !world.map.terrain_at(pos).is_buildable())
+        .expect("the map should have water or mountains");
+    let command = Command::AddNode { pos: unbuildable };
+    assert!(world.execute(&command).is_err());
+    assert_eq!(world.tracks.nodes.len(), nodes);
+    let buildable = spots
+        .clone()
+        .find(|&pos| world.map.terrain_at(pos).is_buildable())
+        .unwrap();
+    let command = Command::AddNode { pos: buildable };
+    assert!(world.execute(&command).is_ok());
+    assert_eq!(world.tracks.nodes.len(), nodes + 1);
+}
+
+#[test]
+fn deterministic() {
+    let mut first = load(IRON_AGE);
+    let mut second = load(IRON_AGE);
+    run(&mut first, 1000);
+    run(&mut second, 1000);
+    assert_eq!(snapshot(&first), snapshot(&second));
+}
+
+#[test]
+fn replay() {
+    let mut world = load(IRON_AGE);
+    let track = world.tracks.nearest_segment(vec2(20.0, 0.0), 5.0).unwrap();
+    let (start, end) = (vec2(-20.0, -20.0), vec2(20.0, -20.0));
+    let commands = [
+        (
+            100,
+            Command::SpawnTrain {
+                wagon: 1,
+                schedule: Vec::new(),
+            },
+        ),
+        (
+            200,
+            Command::PlaceStation {
+                site: StationSite::Track(track),
+            },
+        ),
+        (
+            300,
+            Command::BuildTrack {
+                start: TrackEnd::New(start),
+                end: TrackEnd::New(end),
+                handles: curve_handles(start, None, end, None),
+            },
+        ),
+        (
+            400,
+            Command::SpawnFactory {
+                pos: vec2(0.0, -40.0),
+                angle: 90.0,
+                ty: 2,
+            },
+        ),
+    ];
+    let mut recording = Replay::new(&world, Some(PathBuf::from(IRON_AGE)), TICK);
+    recording.commands = commands
+        .into_iter()
+        .map(|(tick, command)| RecordedCommand { tick, command })
+        .collect();
+    play(&mut world, &mut recording.commands.clone().into(), 1000);
+    assert_eq!(world.trains.len(), 3);
+    assert_eq!(world.stations.len(), 1);
+
+    let saved = serde_json::to_string(&recording).unwrap();
+    let recording: Replay = serde_json::from_str(&saved).unwrap();
+    let scenario = recording.scenario.as_ref().unwrap().to_str().unwrap();
+    let mut replayed = load_seeded(scenario, Some(recording.seed));
+    play(&mut replayed, &mut recording.commands.into(), 1000);
+    assert_eq!(snapshot(&world), snapshot(&replayed));
+}
+
+#[test]
+fn broken_replay() {
+    // Commands pointing at things that don't exist fail instead of bringing the game down
+    let mut world = load(IRON_AGE);
+    let track = world.tracks.nearest_segment(vec2(20.0, 0.0), 5.0).unwrap();
+    let missing = world.id_gen.gen();
+    let handles = [vec2(1.0, 0.0), vec2(-1.0, 0.0)];
+    let commands = [
+        Command::SpawnTrain {
+            wagon: world.config.train.wagon.len(),
+            schedule: Vec::new(),
+        },
+        Command::SpawnFactory {
+            pos: vec2(0.0, -40.0),
+            angle: 0.0,
+            ty: world.factory_types.factory.len(),
+        },
+        Command::BuildTrack {
+            start: TrackEnd::New(vec2(-20.0, -20.0)),
+            end: TrackEnd::Node(missing),
+            handles,
+        },
+        Command::BuildTrack {
+            start: TrackEnd::Node(missing),
+            end: TrackEnd::Node(track.from),
+            handles,
+        },
+        Command::PlaceStation {
+            site: StationSite::Node(missing),
+        },
+        Command::PlaceStation {
+            site: StationSite::Track(TrackPoint {
+                to: missing,
+                ..track
+            }),
+        },
+        Command::PlaceStation {
+            site: StationSite::Track(TrackPoint {
+                ratio: 2.0,
+                ..track
+            }),
+        },
+        Command::UpgradeLoader { node: missing },
+    ];
+    let (nodes, balance) = (world.tracks.nodes.len(), world.wallet.balance());
+    for command in &commands {
+        assert!(world.execute(command).is_err(), "{command:?} went through");
+    }
+    assert_eq!(world.tracks.nodes.len(), nodes);
+    assert_eq!(world.wallet.balance(), balance);
+    let mut pending = commands
+        .into_iter()
+        .map(|command| RecordedCommand { tick: 10, command })
+        .collect();
+    play(&mut world, &mut pending, 300);
+}
+
+#[test]
+fn redundant_tracks() {
+    // Tracks that would add nothing are turned down without charging for them
+    let mut world = load(IRON_AGE);
+    let balance = world.wallet.balance();
+    let track = world.tracks.nearest_segment(vec2(-20.0, 0.0), 5.0).unwrap();
+    let (a, b) = (track.from, track.to);
+    let [a_pos, b_pos] = [a, b].map(|node| world.tracks.nodes.get(&node).unwrap().pos);
+    for (start, end) in [(a, b), (b, a), (a, a)] {
+        let handles = curve_handles(a_pos, None, b_pos, None);
+        let built = world.build_track(TrackEnd::Node(start), TrackEnd::Node(end), handles);
+        assert!(built.is_err());
+    }
+    assert_eq!(world.wallet.balance(), balance);
+}
+
+#[test]
+fn bulk_edit() {
+    let mut world = load(IRON_AGE);
+    run(&mut world, 300);
+    let rect =
+        |center: vec2<f32>, half: vec2<f32>| Aabb2::from_corners(center - half, center + half);
+
+    // Moving a node stretches its tracks, trains on them included
+    let middle = world.select(rect(vec2(-20.0, 0.0), vec2(2.0, 2.0)));
+    assert_eq!(middle.nodes.len(), 1);
+    world.move_selection(&middle, vec2(0.0, 5.0)).unwrap();
+    assert_eq!(
+        world.tracks.nodes.get(&middle.nodes[0]).unwrap().pos,
+        vec2(-20.0, 5.0)
+    );
+    run(&mut world, 600);
+
+    // A copy that can't be built as a whole isn't built at all, here because there is no ore
+    // for the mine
+    let mine = world.select(rect(vec2(-40.0, 0.0), vec2(10.0, 10.0)));
+    assert_eq!(mine.factories.len(), 1);
+    let (nodes, balance) = (world.tracks.nodes.len(), world.wallet.balance());
+    assert_eq!(
+        world.copy_selection(&mine, vec2(0.0, -40.0)),
+        Err("Has to be built on Iron Ore".to_owned())
+    );
+    assert_eq!(world.tracks.nodes.len(), nodes);
+    assert_eq!(world.wallet.balance(), balance);
+
+    // Cutting the line ahead of a train sends it looking for another target
+    let middle = world.tracks.nearest_node(vec2(20.0, 0.0), 1.0).unwrap();
+    let hole = world.tracks.nearest_node(vec2(35.0, 0.0), 1.0).unwrap();
+    let uses_cut = |train: &Train| {
+        let ahead = train
+            .path_from_target
+            .iter()
+            .flat_map(|path| path.iter().rev());
+        let tail = train.tail_nodes.iter();
+        std::iter::once(&train.head.to)
+            .chain(ahead)
+            .tuple_windows()
+            .chain(tail.tuple_windows())
+            .any(|(&a, &b)| Tracks::segment_key(a, b) == Tracks::segment_key(middle, hole))
+    };
+    // Wait for the plates to be on their way
+    while !world.trains.iter().any(uses_cut) {
+        assert!(world.tick < 10000, "No train ever headed through the cut");
+        let tick = world.tick + 1;
+        run(&mut world, tick);
+    }
+    let relying: Vec<Id> = world
+        .trains
+        .iter()
+        .filter(|train| uses_cut(train))
+        .map(|train| train.id)
+        .collect();
+    let cut = Selection {
+        segments: vec![Tracks::segment_key(middle, hole)],
+        ..default()
+    };
+    world.delete(&cut).unwrap();
+    assert!(!world.tracks.curves.contains_key(&cut.segments[0]));
+    for id in &relying {
+        let train = world.trains.get(id).unwrap();
+        assert!(train.target.is_none(), "Train {} kept its target", id.0);
+    }
+    let tick = world.tick + 300;
+    run(&mut world, tick);
+    for train in &world.trains {
+        assert!(!uses_cut(train), "Train {} uses the cut track", train.id.0);
+    }
+    // The plates have nowhere left to go
+    for id in &relying {
+        let train = world.trains.get(id).unwrap();
+        assert!(train.target.is_none(), "Train {} found a target", id.0);
+    }
+
+    // A station on an io goes with its factory
+    let smelter = world.select(rect(vec2(0.0, 0.0), vec2(2.0, 2.0)));
+    assert_eq!(smelter.factories.len(), 1);
+    let io = world.factories.get(&smelter.factories[0]).unwrap().io[0].node;
+    world.place_station(StationSite::Node(io)).unwrap();
+    world.delete(&smelter).unwrap();
+    assert!(world.stations.iter().all(|station| station.node != io));
+    let tick = world.tick + 300;
+    run(&mut world, tick);
+}
+
+#[test]
+fn station_names() {
+    let mut world = load(IRON_AGE);
+    let [first, second] =
+        [vec2(-20.0, 0.0), vec2(20.0, 0.0)].map(|pos| world.tracks.nearest_node(pos, 1.0).unwrap());
+    let name = |world: &World, node: Id| {
+        let station = world.stations.iter().find(|station| station.node == node);
+        station.unwrap().name.clone()
+    };
+    world.place_station(StationSite::Node(first)).unwrap();
+    let removed = name(&world, first);
+    let selection = Selection {
+        nodes: vec![first],
+        ..default()
+    };
+    world.delete(&selection).unwrap();
+    // The name of the removed station isn't handed out again
+    world.place_station(StationSite::Node(second)).unwrap();
+    assert_ne!(name(&world, second), removed);
+}
+
+#[test]
+fn blueprint() {
+    let mut world = load(IRON_AGE);
+    run(&mut world, 300);
+
+    // A smelter with its ios survives saving and comes back turned and flipped
+    let smelter = world.select(Aabb2::from_corners(vec2(-6.0, -3.0), vec2(6.0, 3.0)));
+    assert_eq!(smelter.factories.len(), 1);
+    let blueprint = world.capture(&smelter, vec2::ZERO);
+    assert_eq!(blueprint.factories.len(), 1);
+    let json = serde_json::to_string(&blueprint).unwrap();
+    let blueprint: Blueprint = serde_json::from_str(&json).unwrap();
+    let (factories, balance) = (world.factories.len(), world.wallet.balance());
+    let placement = Placement {
+        pos: vec2(0.0, 40.0),
+        rotation: 90.0,
+        mirror: true,
+    };
+    world.paste(&blueprint, placement).unwrap();
+    assert_eq!(world.factories.len(), factories + 1);
+    assert!(world.wallet.balance() < balance);
+
+    // Ends landing next to existing nodes join them
+    let branch = Blueprint {
+        nodes: vec![vec2(0.0, 0.0), vec2(0.0, -10.0)],
+        tracks: vec![BlueprintTrack {
+            from: 0,
+            to: 1,
+            handles: curve_handles(vec2(0.0, 0.0), None, vec2(0.0, -10.0), None),
+        }],
+        ..default()
+    };
+    let nodes = world.tracks.nodes.len();
+    let placement = Placement {
+        pos: vec2(-20.0, 0.5),
+        ..default()
+    };
+    world.paste(&branch, placement).unwrap();
+    assert_eq!(world.tracks.nodes.len(), nodes + 1);
+
+    // Broken blueprints are turned down before anything is built
+    let mut dangling = branch.clone();
+    dangling.tracks[0].to = 2;
+    let mut missing_io = blueprint.clone();
+    missing_io.factories[0].ios.pop();
+    let balance = world.wallet.balance();
+    let placement = Placement {
+        pos: vec2(0.0, -40.0),
+        ..default()
+    };
+    for broken in [dangling, missing_io] {
+        assert!(world.paste(&broken, placement).is_err());
+    }
+    assert_eq!(world.wallet.balance(), balance);
+    run(&mut world, 600);
+}
+
+#[test]
+fn speed_limits() {
+    let mut world = load(IRON_AGE);
+    // A straight line and a detour around it, away from the scenario's network
+    let mut node = |pos| {
+        world
+            .tracks
+            .add_node(TrackNode::new(&mut world.id_gen, pos))
+    };
+    let start = node(vec2(1000.0, 1000.0));
+    let detour = node(vec2(1020.0, 1020.0));
+    let end = node(vec2(1040.0, 1000.0));
+    for (a, b) in [(start, end), (start, detour), (detour, end)] {
+        world.tracks.add_connection(a, b);
+    }
+    let route = |world: &World| {
+        let (path, _cost) = world
+            .tracks
+            .route(
+                start,
+                None,
+                end,
+                &Congestion::default(),
+                world.config.test.train_speed,
+                &world.config.track,
+            )
+            .unwrap();
+        path
+    };
+    assert_eq!(route(&world), vec![start, end]);
+
+    // Over a slow bridge the straight line is shorter but takes longer
+    world.tracks.set_kind(start, end, SegmentKind::Bridge);
+    let bridge = &world.config.track.bridge;
+    assert!(40.0 / bridge.speed_limit > 2.0 * 20.0 * 2f32.sqrt() / world.config.test.train_speed);
+    assert_eq!(route(&world), vec![start, detour, end]);
+}
+
+#[test]
+fn move_tool() {
+    let mut world = load(IRON_AGE);
+    run(&mut world, 300);
+
+    // Grabbing an io takes its whole factory along, tracks to it stretching to follow
+    let Some(Grab::Factory(smelter)) = world.grab(vec2(5.0, 0.0), 1.0) else {
+        panic!("the smelter io should grab the smelter");
+    };
+    let length = world.tracks.segment_length(
+        world.tracks.nearest_node(vec2(20.0, 0.0), 1.0).unwrap(),
+        world.tracks.nearest_node(vec2(5.0, 0.0), 1.0).unwrap(),
+    );
+    let balance = world.wallet.balance();
+    world
+        .move_selection(&Grab::Factory(smelter).selection(), vec2(0.0, -10.0))
+        .unwrap();
+    // Longer track costs more
+    assert!(world.wallet.balance() < balance);
+    let factory = world.factories.get(&smelter).unwrap();
+    assert_eq!(factory.pos, vec2(0.0, -10.0));
+    assert!(factory.io.iter().all(|io| (io.pos.y + 10.0).abs() < 1e-3));
+    let io = factory.io.iter().find(|io| io.pos.x > 0.0).unwrap().node;
+    let middle = world.tracks.nearest_node(vec2(20.0, 0.0), 1.0).unwrap();
+    assert!(world.tracks.segment_length(middle, io) > length);
+    run(&mut world, 300);
+
+    // A lone node goes by itself
+    let Some(Grab::Node(node)) = world.grab(vec2(-20.0, 0.0), 1.0) else {
+        panic!("the middle node should be grabbed on its own");
+    };
+    world
+        .move_selection(&Grab::Node(node).selection(), vec2(0.0, 5.0))
+        .unwrap();
+    assert_eq!(world.tracks.nodes.get(&node).unwrap().pos, vec2(-20.0, 5.0));
+    run(&mut world, 300);
+}
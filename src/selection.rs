@@ -0,0 +1,709 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::*;
+
+/// Things picked with a box, kept by id so that commands made from it replay the same
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub(crate) struct Selection {
+    pub nodes: Vec<Id>,
+    /// Keyed by [Tracks::segment_key]
+    pub segments: Vec<(Id, Id)>,
+    pub factories: Vec<Id>,
+    pub trains: Vec<Id>,
+}
+
+impl Selection {
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+            && self.segments.is_empty()
+            && self.factories.is_empty()
+            && self.trains.is_empty()
+    }
+
+    /// Forget whatever is gone from the world
+    pub fn retain_existing(&mut self, world: &World) {
+        self.nodes.retain(|id| world.tracks.nodes.get(id).is_some());
+        self.segments
+            .retain(|key| world.tracks.curves.contains_key(key));
+        self.factories
+            .retain(|id| world.factories.get(id).is_some());
+        self.trains.retain(|id| world.trains.get(id).is_some());
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "Selected {} nodes, {} tracks, {} factories, {} trains",
+            self.nodes.len(),
+            self.segments.len(),
+            self.factories.len(),
+            self.trains.len()
+        )
+    }
+}
+
+/// Single node or factory picked up with the move tool
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Grab {
+    Node(Id),
+    Factory(Id),
+}
+
+impl Grab {
+    pub fn selection(self) -> Selection {
+        match self {
+            Grab::Node(id) => Selection {
+                nodes: vec![id],
+                ..default()
+            },
+            Grab::Factory(id) => Selection {
+                factories: vec![id],
+                ..default()
+            },
+        }
+    }
+}
+
+/// Direction of the first io from the center, which the others are spread from
+pub fn factory_angle(factory: &Factory) -> Angle<f32> {
+    factory
+        .io
+        .first()
+        .map_or(Angle::ZERO, |io| (io.pos - factory.pos).arg())
+}
+
+/// Whether a train can stand between `a` and `b`, the same node meaning it stands at it
+fn connected(tracks: &Tracks, a: Id, b: Id) -> bool {
+    tracks
+        .nodes
+        .get(&a)
+        .is_some_and(|node| a == b || node.connections.contains(&b))
+}
+
+/// What moving a selection amounts to, worked out before anything moves
+struct MovePlan {
+    /// Nodes that go along, see [World::carried_nodes]
+    nodes: BTreeSet<Id>,
+    /// Every track to a carried node, planned with its ends where they end up
+    segments: BTreeMap<(Id, Id), TrackPlan>,
+    /// What the stretched tracks cost more than they do now. Nothing comes back for
+    /// tracks that get cheaper.
+    price: f32,
+}
+
+impl World {
+    /// Everything inside `rect`, tracks only if they are inside as a whole
+    pub fn select(&self, rect: Aabb2<f32>) -> Selection {
+        Selection {
+            nodes: self
+                .tracks
+                .nodes_in_rect(rect)
+                .into_iter()
+                .sorted()
+                .collect(),
+            segments: self
+                .tracks
+                .segments_in_rect(rect)
+                .into_iter()
+                .filter(|key| {
+                    self.tracks.curves[key]
+                        .points()
+                        .iter()
+                        .all(|&pos| rect.contains(pos))
+                })
+                .sorted()
+                .collect(),
+            factories: self
+                .factories
+                .iter()
+                .filter(|factory| rect.contains(factory.pos))
+                .map(|factory| factory.id)
+                .sorted()
+                .collect(),
+            trains: self
+                .trains
+                .iter()
+                .filter(|train| rect.contains(self.tracks.point_pos(train.head)))
+                .map(|train| train.id)
+                .sorted()
+                .collect(),
+        }
+    }
+
+    /// Factory the io at `node` belongs to
+    fn io_owner(&self, node: Id) -> Option<Id> {
+        self.factories
+            .iter()
+            .find(|factory| factory.io.iter().any(|io| io.node == node))
+            .map(|factory| factory.id)
+    }
+
+    /// Node within `grab_distance` of `pos` or factory under it. Ios can't leave their
+    /// factory, so grabbing one grabs the factory.
+    pub fn grab(&self, pos: vec2<f32>, grab_distance: f32) -> Option<Grab> {
+        if let Some(id) = self.tracks.nearest_node(pos, grab_distance) {
+            return Some(self.io_owner(id).map_or(Grab::Node(id), Grab::Factory));
+        }
+        self.factories
+            .iter()
+            .filter(|factory| (factory.pos - pos).len() <= self.factory_types[factory.ty].radius)
+            .min_by_key(|factory| factory.id)
+            .map(|factory| Grab::Factory(factory.id))
+    }
+
+    pub fn grab_pos(&self, grab: Grab) -> Option<vec2<f32>> {
+        match grab {
+            Grab::Node(id) => self.tracks.nodes.get(&id).map(|node| node.pos),
+            Grab::Factory(id) => self.factories.get(&id).map(|factory| factory.pos),
+        }
+    }
+
+    /// Nodes that go along when the selection is moved or copied: the selected ones, the ends
+    /// of selected tracks and the ios of selected factories, but not the ios of factories
+    /// left behind
+    pub fn carried_nodes(&self, selection: &Selection) -> BTreeSet<Id> {
+        let mut nodes: BTreeSet<Id> = selection
+            .nodes
+            .iter()
+            .copied()
+            .chain(selection.segments.iter().flat_map(|&(a, b)| [a, b]))
+            .filter(|&id| self.tracks.nodes.get(&id).is_some())
+            .filter(|&id| {
+                self.io_owner(id)
+                    .is_none_or(|factory| selection.factories.contains(&factory))
+            })
+            .collect();
+        for id in &selection.factories {
+            if let Some(factory) = self.factories.get(id) {
+                nodes.extend(factory.io.iter().map(|io| io.node));
+            }
+        }
+        nodes
+    }
+
+    fn remove_train(&mut self, id: Id) {
+        if let Some(train) = self.trains.remove(&id) {
+            if let Some(target) = train.target {
+                self.queues.leave(target.node(), id);
+            }
+        }
+    }
+
+    /// Remove everything selected. Nodes go with their tracks and stations, factories with
+    /// their io nodes, and trains left on removed track are removed too.
+    pub fn delete(&mut self, selection: &Selection) -> Result<(), String> {
+        if selection.is_empty() {
+            return Err("Nothing selected".to_owned());
+        }
+        for &id in &selection.trains {
+            self.remove_train(id);
+        }
+        for id in &selection.factories {
+            if let Some(factory) = self.factories.remove(id) {
+                for io in factory.io {
+                    self.tracks.remove_node(io.node);
+                }
+            }
+        }
+        for &(a, b) in &selection.segments {
+            if self.tracks.nodes.get(&a).is_some() {
+                self.tracks.remove_connection(a, b);
+            }
+        }
+        for &id in &selection.nodes {
+            // An io stays as long as its factory does
+            if self.io_owner(id).is_some() {
+                continue;
+            }
+            self.tracks.remove_node(id);
+        }
+        // Stations go with their node, ios of removed factories included
+        let tracks = &self.tracks;
+        self.stations
+            .retain(|station| tracks.nodes.get(&station.node).is_some());
+        self.settle_trains();
+        Ok(())
+    }
+
+    /// Check that a factory of type `ty` can stand at `pos`
+    pub fn check_factory_site(&self, pos: vec2<f32>, ty: usize) -> Result<(), String> {
+        let factory_type = &self.factory_types[ty];
+        let terrain = self.map.terrain_at(pos);
+        if !terrain.is_buildable() {
+            return Err(format!("Can't build on {}", terrain.name()));
+        }
+        if let Some(resource) = &factory_type.deposit {
+            let on_deposit = self
+                .deposit_type(resource)
+                .is_some_and(|ty| self.map.deposit_amount(ty, pos, factory_type.radius) > 0.0);
+            if !on_deposit {
+                return Err(format!("Has to be built on {resource}"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Tracks with both ends among `nodes`, with their plans once the ends are moved by `offset`
+    fn plan_carried_segments(
+        &self,
+        nodes: &BTreeSet<Id>,
+        all_connections: bool,
+        offset: vec2<f32>,
+    ) -> Result<BTreeMap<(Id, Id), TrackPlan>, String> {
+        let segments: BTreeSet<(Id, Id)> = nodes
+            .iter()
+            .flat_map(|&id| {
+                let node = self.tracks.nodes.get(&id).unwrap();
+                node.connections
+                    .iter()
+                    .filter(|other| all_connections || nodes.contains(other))
+                    .map(move |&other| Tracks::segment_key(id, other))
+            })
+            .collect();
+        let pos = |id: Id| {
+            let pos = self.tracks.nodes.get(&id).unwrap().pos;
+            if nodes.contains(&id) {
+                pos + offset
+            } else {
+                pos
+            }
+        };
+        segments
+            .into_iter()
+            .map(|(a, b)| {
+                let handles = self.tracks.curves[&(a, b)].handles;
+                let plan = self.plan_track(pos(a), pos(b), handles)?;
+                Ok(((a, b), plan))
+            })
+            .collect()
+    }
+
+    /// Check that the selection can be moved by `offset`
+    fn plan_move(&self, selection: &Selection, offset: vec2<f32>) -> Result<MovePlan, String> {
+        let nodes = self.carried_nodes(selection);
+        if nodes.is_empty() && selection.factories.is_empty() {
+            return Err("Nothing to move".to_owned());
+        }
+        for factory in selection
+            .factories
+            .iter()
+            .filter_map(|id| self.factories.get(id))
+        {
+            self.check_factory_site(factory.pos + offset, factory.ty)?;
+        }
+        let segments = self.plan_carried_segments(&nodes, true, offset)?;
+        let current: f32 = segments
+            .keys()
+            .map(|&(a, b)| {
+                let pos = |id: Id| self.tracks.nodes.get(&id).unwrap().pos;
+                let handles = self.tracks.curves[&(a, b)].handles;
+                self.plan_track(pos(a), pos(b), handles)
+                    .map_or(0.0, |plan| plan.price)
+            })
+            .sum();
+        let moved: f32 = segments.values().map(|plan| plan.price).sum();
+        Ok(MovePlan {
+            nodes,
+            segments,
+            price: (moved - current).max(0.0),
+        })
+    }
+
+    /// Move the selection by `offset`, tracks to nodes left behind stretching to follow and
+    /// paying for what they cost more than before
+    pub fn move_selection(
+        &mut self,
+        selection: &Selection,
+        offset: vec2<f32>,
+    ) -> Result<(), String> {
+        // Check everything first, so that a move that fails changes nothing
+        let MovePlan {
+            nodes,
+            segments,
+            price,
+        } = self.plan_move(selection, offset)?;
+        self.pay(price)?;
+
+        for id in &selection.factories {
+            if let Some(factory) = self.factories.get_mut(id) {
+                factory.pos += offset;
+                for io in &mut factory.io {
+                    io.pos += offset;
+                }
+            }
+        }
+        for &id in &nodes {
+            let pos = self.tracks.nodes.get(&id).unwrap().pos;
+            self.tracks.move_node(id, pos + offset);
+        }
+        for ((a, b), plan) in segments {
+            self.tracks.set_kind(a, b, plan.kind);
+        }
+        self.settle_trains();
+        Ok(())
+    }
+
+    /// Pay for and build a copy of the selection moved by `offset`, including the stations on
+    /// copied nodes but not the trains
+    pub fn copy_selection(
+        &mut self,
+        selection: &Selection,
+        offset: vec2<f32>,
+    ) -> Result<(), String> {
+        let nodes = self.carried_nodes(selection);
+        if nodes.is_empty() && selection.factories.is_empty() {
+            return Err("Nothing to copy".to_owned());
+        }
+        let factories: Vec<Id> = selection
+            .factories
+            .iter()
+            .copied()
+            .filter(|id| self.factories.get(id).is_some())
+            .collect();
+        let stations: Vec<Id> = self
+            .stations
+            .iter()
+            .filter(|station| nodes.contains(&station.node))
+            .map(|station| station.node)
+            .sorted()
+            .collect();
+        let mut price = stations.len() as f32 * self.config.economy.station_price;
+        for id in &factories {
+            let factory = self.factories.get(id).unwrap();
+            self.check_factory_site(factory.pos + offset, factory.ty)?;
+            price += self.factory_types[factory.ty].price;
+        }
+        let segments = self.plan_carried_segments(&nodes, false, offset)?;
+        price += segments.values().map(|plan| plan.price).sum::<f32>();
+        self.pay(price)?;
+
+        let mut copies: HashMap<Id, Id> = HashMap::new();
+        for id in factories {
+            let factory = self.factories.get(&id).unwrap();
+            let (pos, angle, ty) = (factory.pos, factory_angle(factory), factory.ty);
+            let ios: Vec<Id> = factory.io.iter().map(|io| io.node).collect();
+            let copy = self.build_factory(pos + offset, angle, ty);
+            let copy = self.factories.get(&copy).unwrap();
+            copies.extend(ios.into_iter().zip(copy.io.iter().map(|io| io.node)));
+        }
+        for &id in &nodes {
+            let pos = self.tracks.nodes.get(&id).unwrap().pos;
+            copies
+                .entry(id)
+                .or_insert_with(|| self.add_node(pos + offset));
+        }
+        for ((a, b), plan) in segments {
+            let handles = self.tracks.curves[&(a, b)].handles;
+            let (a, b) = (copies[&a], copies[&b]);
+            self.tracks.add_curve(a, b, handles);
+            self.tracks.set_kind(a, b, plan.kind);
+        }
+        for node in stations {
+            let name = self.next_station_name();
+            self.build_station(copies[&node], name);
+        }
+        Ok(())
+    }
+
+    /// Bring trains in line after the tracks changed under them: remove the ones standing on
+    /// track that is gone, send the ones whose way or target is gone looking for a new target,
+    /// and cut tails that became longer than their train
+    pub fn settle_trains(&mut self) {
+        let stranded: Vec<Id> = self
+            .trains
+            .iter()
+            .filter(|train| {
+                !connected(&self.tracks, train.head.from, train.head.to)
+                    || train
+                        .tail_nodes
+                        .iter()
+                        .tuple_windows()
+                        .any(|(&a, &b)| !connected(&self.tracks, a, b))
+            })
+            .map(|train| train.id)
+            .sorted()
+            .collect();
+        for id in stranded {
+            self.remove_train(id);
+        }
+        for train in &mut self.trains {
+            let target_gone = train.target.is_some_and(|target| match target {
+                Target::Io { io, .. } => self.factories.get(&io.factory).is_none(),
+                Target::Station { id, .. } => self.stations.get(&id).is_none(),
+            });
+            let way_gone = train.path_from_target.as_ref().is_some_and(|path| {
+                std::iter::once(train.head.to)
+                    .chain(path.iter().rev().copied())
+                    .tuple_windows()
+                    .any(|(a, b)| !connected(&self.tracks, a, b))
+            });
+            if target_gone || way_gone {
+                if let Some(target) = train.target.take() {
+                    self.queues.leave(target.node(), train.id);
+                }
+                train.path_from_target = None;
+                train.dwell = 0.0;
+            }
+            let mut covered_length =
+                train.head.ratio * self.tracks.segment_length(train.head.from, train.head.to);
+            for (i, (a, b)) in train.tail_nodes.iter().copied().tuple_windows().enumerate() {
+                if covered_length > train.length {
+                    train.tail_nodes.truncate(i + 1);
+                    break;
+                }
+                covered_length += self.tracks.segment_length(a, b);
+            }
+        }
+    }
+}
+
+impl Game {
+    /// Box between where the selection drag started and the cursor
+    pub(crate) fn selection_rect(&self, start: vec2<f32>) -> Aabb2<f32> {
+        Aabb2::from_corners(start, self.cursor_world_position)
+    }
+
+    /// Whether a drag starting at `pos` grabs the selection rather than laying track
+    pub(crate) fn grabs_selection(&self, pos: vec2<f32>) -> bool {
+        let grab = self.ui_to_world_distance(self.config.control.snap_distance);
+        let near = |other: vec2<f32>| (other - pos).len() <= grab;
+        let selection = &self.selection;
+        selection
+            .nodes
+            .iter()
+            .filter_map(|id| self.world.tracks.nodes.get(id))
+            .any(|node| near(node.pos))
+            || selection
+                .factories
+                .iter()
+                .filter_map(|id| self.world.factories.get(id))
+                .any(|factory| (factory.pos - pos).len() <= self.factory_types[factory.ty].radius)
+            || selection.segments.iter().any(|&(a, b)| {
+                self.world
+                    .tracks
+                    .nearest_segment(pos, grab)
+                    .is_some_and(|point| Tracks::segment_key(point.from, point.to) == (a, b))
+            })
+    }
+
+    /// Where the selection being dragged from `start` ends up, snapped to the grid if that
+    /// is on
+    pub(crate) fn selection_offset(&self, start: vec2<f32>) -> vec2<f32> {
+        self.snapping.apply(
+            &self.config.drawing.snapping,
+            self.cursor_world_position - start,
+            None,
+        )
+    }
+
+    /// Node or factory a move tool drag starting at `pos` picks up
+    pub(crate) fn grab_at(&self, pos: vec2<f32>) -> Option<Grab> {
+        self.world.grab(
+            pos,
+            self.ui_to_world_distance(self.config.control.snap_distance),
+        )
+    }
+
+    /// How far `grab` dragged from `start` gets moved. Unlike the selection, the grabbed
+    /// thing itself snaps rather than the distance it is dragged: a node in angle and length
+    /// to its neighbour closest to the cursor, like the end of a track drawn from there, and
+    /// a factory or a node without neighbours to the grid.
+    pub(crate) fn grab_offset(&self, grab: Grab, start: vec2<f32>) -> vec2<f32> {
+        let Some(pos) = self.world.grab_pos(grab) else {
+            return vec2::ZERO;
+        };
+        let target = pos + self.cursor_world_position - start;
+        let origin = match grab {
+            Grab::Node(id) => self.grab_origin(id, target),
+            Grab::Factory(_) => None,
+        };
+        self.snapping
+            .apply(&self.config.drawing.snapping, target, origin)
+            - pos
+    }
+
+    /// Neighbour of node `id` closest to `pos` and the direction of the track there that
+    /// is closest to pointing at `pos`, other than the one to `id` itself
+    fn grab_origin(&self, id: Id, pos: vec2<f32>) -> Option<(vec2<f32>, Option<vec2<f32>>)> {
+        let tracks = &self.world.tracks;
+        let neighbour = tracks
+            .nodes
+            .get(&id)?
+            .connections
+            .iter()
+            .filter_map(|other| tracks.nodes.get(other))
+            .min_by_key(|neighbour| (r32((neighbour.pos - pos).len()), neighbour.id))?;
+        let towards = (pos - neighbour.pos).normalize_or_zero();
+        let reference = neighbour
+            .connections
+            .iter()
+            .filter(|&&other| other != id)
+            .map(|&other| {
+                let (curve, reversed) = tracks.curve(neighbour.id, other);
+                -curve.handles[reversed as usize].normalize_or_zero()
+            })
+            .max_by_key(|&dir| r32(vec2::dot(dir, towards)));
+        Some((neighbour.pos, reference))
+    }
+
+    /// Box around the selected nodes and factories
+    pub(crate) fn selection_bounds(&self) -> Option<Aabb2<f32>> {
+        let positions: Vec<vec2<f32>> = self
+            .selection
+            .nodes
+            .iter()
+            .filter_map(|id| self.world.tracks.nodes.get(id))
+            .map(|node| node.pos)
+            .chain(
+                self.selection
+                    .factories
+                    .iter()
+                    .filter_map(|id| self.world.factories.get(id))
+                    .map(|factory| factory.pos),
+            )
+            .collect();
+        Aabb2::points_bounding_box(positions)
+    }
+
+    /// Build a copy of the selection centered on the cursor
+    pub(crate) fn copy_selection_to_cursor(&mut self) {
+        let Some(bounds) = self.selection_bounds() else {
+            return;
+        };
+        let offset = self.snapping.apply(
+            &self.config.drawing.snapping,
+            self.cursor_world_position - bounds.center(),
+            None,
+        );
+        self.command(Command::Copy {
+            selection: self.selection.clone(),
+            offset,
+        });
+    }
+
+    pub(crate) fn draw_selection(&self, framebuffer: &mut ugli::Framebuffer) {
+        let offset = match self.control {
+            Control::MovingSelection { start } => self.selection_offset(start),
+            _ => vec2::ZERO,
+        };
+        self.draw_selected(framebuffer, &self.selection, offset);
+        if let Control::MovingObject { grab, start } = self.control {
+            let offset = self.grab_offset(grab, start);
+            self.draw_stretched(framebuffer, &grab.selection(), offset);
+            self.draw_selected(framebuffer, &grab.selection(), offset);
+        }
+        if let Control::Selecting { start } = self.control {
+            self.geng.draw2d().draw2d(
+                framebuffer,
+                &self.camera,
+                &draw2d::Quad::new(
+                    self.selection_rect(start),
+                    self.config.drawing.selection_box_color,
+                ),
+            );
+        }
+    }
+
+    /// Tracks from `selection` to what is left behind, as they would run once the selection
+    /// is moved by `offset`, in the invalid color if the move can't be done
+    fn draw_stretched(
+        &self,
+        framebuffer: &mut ugli::Framebuffer,
+        selection: &Selection,
+        offset: vec2<f32>,
+    ) {
+        let world = &self.world;
+        let color = match world.plan_move(selection, offset) {
+            Ok(_) => self.config.drawing.preview_color,
+            Err(_) => self.config.drawing.invalid_color,
+        };
+        let nodes = world.carried_nodes(selection);
+        let pos = |id: Id| {
+            let pos = world.tracks.nodes.get(&id).unwrap().pos;
+            if nodes.contains(&id) {
+                pos + offset
+            } else {
+                pos
+            }
+        };
+        for &id in &nodes {
+            let node = world.tracks.nodes.get(&id).unwrap();
+            for &other in node
+                .connections
+                .iter()
+                .filter(|other| !nodes.contains(other))
+            {
+                let (a, b) = Tracks::segment_key(id, other);
+                let curve = Curve::new(pos(a), pos(b), world.tracks.curves[&(a, b)].handles);
+                self.geng.draw2d().draw2d(
+                    framebuffer,
+                    &self.camera,
+                    &draw2d::Chain::new(curve.points().to_vec(), self.config.track.width, color, 4),
+                );
+            }
+        }
+    }
+
+    /// Highlight `selection`, moved by `offset` while it is being dragged
+    fn draw_selected(
+        &self,
+        framebuffer: &mut ugli::Framebuffer,
+        selection: &Selection,
+        offset: vec2<f32>,
+    ) {
+        let color = self.config.drawing.selection_color;
+        let width = self.config.track.width;
+        for key in &selection.segments {
+            if let Some(curve) = self.world.tracks.curves.get(key) {
+                let points = curve.points().iter().map(|&pos| pos + offset).collect();
+                self.geng.draw2d().draw2d(
+                    framebuffer,
+                    &self.camera,
+                    &draw2d::Chain::new(points, width * 2.0, color, 4),
+                );
+            }
+        }
+        for node in selection
+            .nodes
+            .iter()
+            .filter_map(|id| self.world.tracks.nodes.get(id))
+        {
+            self.geng.draw2d().draw2d(
+                framebuffer,
+                &self.camera,
+                &draw2d::Ellipse::circle(node.pos + offset, width, color),
+            );
+        }
+        for factory in selection
+            .factories
+            .iter()
+            .filter_map(|id| self.world.factories.get(id))
+        {
+            let radius = self.factory_types[factory.ty].radius;
+            self.geng.draw2d().draw2d(
+                framebuffer,
+                &self.camera,
+                &draw2d::Ellipse::circle_with_cut(
+                    factory.pos + offset,
+                    radius,
+                    radius + width,
+                    color,
+                ),
+            );
+        }
+        for train in selection
+            .trains
+            .iter()
+            .filter_map(|id| self.world.trains.get(id))
+        {
+            self.geng.draw2d().draw2d(
+                framebuffer,
+                &self.camera,
+                &draw2d::Ellipse::circle(
+                    self.world.tracks.point_pos(train.head),
+                    self.config.train.width * 2.0,
+                    color,
+                ),
+            );
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use super::*;
+
+/// Which kinds of snapping are toggled on
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Snapping {
+    pub grid: bool,
+    pub angle: bool,
+    pub length: bool,
+}
+
+impl Snapping {
+    /// `origin` is the start of the track being drawn along with the direction of
+    /// the track it continues, which angles are measured from
+    pub fn apply(
+        &self,
+        config: &SnappingConfig,
+        pos: vec2<f32>,
+        origin: Option<(vec2<f32>, Option<vec2<f32>>)>,
+    ) -> vec2<f32> {
+        if let Some((start, reference)) = origin {
+            if self.angle || self.length {
+                let offset = pos - start;
+                let mut angle = offset.y.atan2(offset.x);
+                let mut length = offset.len();
+                if self.angle {
+                    let base = reference.map_or(0.0, |dir| dir.y.atan2(dir.x));
+                    let step = config.angle.to_radians();
+                    angle = base + ((angle - base) / step).round() * step;
+                }
+                if self.length {
+                    length = (length / config.length).round().max(1.0) * config.length;
+                }
+                return start + vec2(angle.cos(), angle.sin()) * length;
+            }
+        }
+        if self.grid {
+            return (pos / config.grid).map(f32::round) * config.grid;
+        }
+        pos
+    }
+}
@@ -0,0 +1,158 @@
+use super::*;
+
+type Cell = (i32, i32);
+
+/// Uniform grid bucketing items by every cell their bounding box overlaps
+pub struct SpatialGrid<T> {
+    cell_size: f32,
+    cells: HashMap<Cell, Vec<T>>,
+    bounds: Option<(Cell, Cell)>,
+}
+
+impl<T: Copy + Eq + std::hash::Hash> SpatialGrid<T> {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+            bounds: None,
+        }
+    }
+
+    fn cell(&self, pos: vec2<f32>) -> Cell {
+        (
+            (pos.x / self.cell_size).floor() as i32,
+            (pos.y / self.cell_size).floor() as i32,
+        )
+    }
+
+    fn cells(&self, aabb: Aabb2<f32>) -> impl Iterator<Item = Cell> {
+        let (min_x, min_y) = self.cell(aabb.min);
+        let (max_x, max_y) = self.cell(aabb.max);
+        (min_x..=max_x).flat_map(move |x| (min_y..=max_y).map(move |y| (x, y)))
+    }
+
+    pub fn insert(&mut self, item: T, aabb: Aabb2<f32>) {
+        for cell in self.cells(aabb).collect::<Vec<_>>() {
+            self.cells.entry(cell).or_default().push(item);
+            self.bounds = Some(match self.bounds {
+                None => (cell, cell),
+                Some((min, max)) => (
+                    (min.0.min(cell.0), min.1.min(cell.1)),
+                    (max.0.max(cell.0), max.1.max(cell.1)),
+                ),
+            });
+        }
+    }
+
+    /// `aabb` must be the same one the item was inserted with
+    pub fn remove(&mut self, item: T, aabb: Aabb2<f32>) {
+        for cell in self.cells(aabb).collect::<Vec<_>>() {
+            if let Some(items) = self.cells.get_mut(&cell) {
+                items.retain(|&other| other != item);
+                if items.is_empty() {
+                    self.cells.remove(&cell);
+                }
+            }
+        }
+    }
+
+    /// Items whose bounding box may overlap `aabb`, without duplicates
+    pub fn query(&self, aabb: Aabb2<f32>) -> Vec<T> {
+        let mut seen = HashSet::new();
+        self.cells(aabb)
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+            .filter(|&item| seen.insert(item))
+            .collect()
+    }
+
+    /// Closest item according to `distance`, searching outwards ring by ring
+    /// and stopping once no unvisited cell can contain anything closer
+    pub fn nearest(
+        &self,
+        pos: vec2<f32>,
+        max_distance: f32,
+        distance: impl Fn(T) -> f32,
+    ) -> Option<T> {
+        let ((min_x, min_y), (max_x, max_y)) = self.bounds?;
+        let center = self.cell(pos);
+        let max_ring = [
+            center.0 - min_x,
+            max_x - center.0,
+            center.1 - min_y,
+            max_y - center.1,
+        ]
+        .into_iter()
+        .max()
+        .unwrap()
+        .max(0);
+        let mut best: Option<(T, f32)> = None;
+        for ring in 0..=max_ring {
+            // Nothing in this ring or further out can be closer than this
+            let reach = (ring - 1).max(0) as f32 * self.cell_size;
+            if reach > max_distance || best.is_some_and(|(_, best)| best <= reach) {
+                break;
+            }
+            for cell in ring_cells(center, ring) {
+                let Some(items) = self.cells.get(&cell) else {
+                    continue;
+                };
+                for &item in items {
+                    let d = distance(item);
+                    if d <= max_distance && best.is_none_or(|(_, best)| d < best) {
+                        best = Some((item, d));
+                    }
+                }
+            }
+        }
+        best.map(|(item, _)| item)
+    }
+}
+
+fn ring_cells((x, y): Cell, ring: i32) -> impl Iterator<Item = Cell> {
+    (-ring..=ring).flat_map(move |dx| {
+        (-ring..=ring)
+            .filter(move |dy| dx.abs() == ring || dy.abs() == ring)
+            .map(move |dy| (x + dx, y + dy))
+    })
+}
+
+/// Ratio along `a`-`b` of the point closest to `p`
+pub fn closest_ratio(a: vec2<f32>, b: vec2<f32>, p: vec2<f32>) -> f32 {
+    let dir = b - a;
+    let len_sqr = vec2::dot(dir, dir);
+    if len_sqr == 0.0 {
+        return 0.0;
+    }
+    (vec2::dot(p - a, dir) / len_sqr).clamp(0.0, 1.0)
+}
+
+pub fn segment_intersects_rect(a: vec2<f32>, b: vec2<f32>, rect: Aabb2<f32>) -> bool {
+    // Liang-Barsky clipping
+    let dir = b - a;
+    let (mut enter, mut exit) = (0.0, 1.0);
+    for (p, q) in [
+        (-dir.x, a.x - rect.min.x),
+        (dir.x, rect.max.x - a.x),
+        (-dir.y, a.y - rect.min.y),
+        (dir.y, rect.max.y - a.y),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return false;
+            }
+            continue;
+        }
+        let t = q / p;
+        if p < 0.0 {
+            enter = f32::max(enter, t);
+        } else {
+            exit = f32::min(exit, t);
+        }
+        if enter > exit {
+            return false;
+        }
+    }
+    true
+}
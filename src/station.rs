@@ -0,0 +1,156 @@
+use super::*;
+
+/// Named stop on the tracks, not tied to any factory
+#[derive(HasId)]
+pub(crate) struct Station {
+    pub id: Id,
+    pub name: String,
+    pub node: Id,
+    pub platform_length: f32,
+    /// Index into [FactoryTypes::loader]
+    pub loader: usize,
+}
+
+impl Station {
+    /// Factory ios for `resource` close enough for this station to feed or draw from,
+    /// in a stable order
+    pub fn nearby_ios(
+        &self,
+        tracks: &Tracks,
+        factories: &Collection<Factory>,
+        factory_types: &FactoryTypes,
+        config: &StationConfig,
+        resource: Id,
+    ) -> Vec<IoId> {
+        let pos = tracks.nodes.get(&self.node).unwrap().pos;
+        let mut ios: Vec<IoId> = factories
+            .iter()
+            .filter(|factory| {
+                (factory.pos - pos).len() <= factory_types[factory.ty].radius + config.feed_radius
+            })
+            .flat_map(|factory| {
+                factory
+                    .io
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, io)| io.resource == resource)
+                    .map(|(io, _)| IoId {
+                        factory: factory.id,
+                        io,
+                    })
+            })
+            .collect();
+        ios.sort_by_key(|io| (io.factory, io.io));
+        ios
+    }
+
+    /// Polyline of the platform, centered on the station node
+    pub fn platform(&self, tracks: &Tracks) -> Vec<vec2<f32>> {
+        let node = tracks.nodes.get(&self.node).unwrap();
+        let half = self.platform_length / 2.0;
+        let mut sides = node.connections.iter().take(2).map(|&other| {
+            let length = tracks.segment_length(node.id, other);
+            tracks.segment_points(node.id, other, 0.0, (half / length).min(1.0))
+        });
+        let mut points = sides.next().unwrap_or_else(|| vec![node.pos]);
+        points.reverse();
+        points.extend(sides.next().into_iter().flatten().skip(1));
+        points
+    }
+}
+
+/// Cargo moved at a station node since the start of the game
+#[derive(Default)]
+pub struct StationStats {
+    pub loaded: f32,
+    pub unloaded: f32,
+    /// Trains that finished loading or unloading
+    pub visits: usize,
+    /// Total time those trains spent at the platform
+    pub dwell: f32,
+    /// Money earned by selling cargo here
+    pub revenue: f32,
+}
+
+/// Move up to `max_amount` of cargo between a train standing at its target and the factory
+/// `ios` it serves: unload into inputs until empty, then load from outputs until full.
+/// Returns whether the train is done.
+pub fn transfer_cargo(
+    train: &mut Train,
+    factories: &mut Collection<Factory>,
+    ios: &[IoId],
+    capacity: f32,
+    max_amount: f32,
+    stats: &mut StationStats,
+    wallet: &mut Wallet,
+) -> bool {
+    let of_type = |ty: IoType| {
+        ios.iter()
+            .copied()
+            .filter(|io| factories.get(&io.factory).unwrap().io[io.io].ty == ty)
+            .collect::<Vec<_>>()
+    };
+    let (inputs, outputs) = (of_type(IoType::Input), of_type(IoType::Output));
+
+    if let Some(input) = inputs.first() {
+        if !train.amount.approx_eq(&0.0) {
+            let io = &mut factories.get_mut(&input.factory).unwrap().io[input.io];
+            let unload_amount = train.amount.min(max_amount);
+            train.amount -= unload_amount;
+            stats.unloaded += unload_amount;
+            io.delivered += unload_amount;
+            if let Some(io_amount) = &mut io.amount {
+                *io_amount += unload_amount;
+            }
+            if let Some(price) = io.sale_price {
+                wallet.earn(unload_amount * price);
+                stats.revenue += unload_amount * price;
+            }
+            return false;
+        }
+    }
+
+    if !outputs.is_empty() && !capacity.approx_eq(&train.amount) {
+        let mut remaining = (capacity - train.amount).min(max_amount);
+        for output in outputs {
+            let io = &mut factories.get_mut(&output.factory).unwrap().io[output.io];
+            let load_amount = io.amount.map_or(remaining, |amount| amount.min(remaining));
+            train.amount += load_amount;
+            stats.loaded += load_amount;
+            remaining -= load_amount;
+            if let Some(io_amount) = &mut io.amount {
+                *io_amount -= load_amount;
+            }
+        }
+        return false;
+    }
+
+    true
+}
+
+/// Trains heading to each station node, in the order they picked it.
+/// Only the first train of a queue uses the platform, the rest hold back before it.
+#[derive(Default)]
+pub struct StationQueues {
+    queues: HashMap<Id, VecDeque<Id>>,
+}
+
+impl StationQueues {
+    pub fn join(&mut self, node: Id, train: Id) {
+        self.queues.entry(node).or_default().push_back(train);
+    }
+
+    pub fn leave(&mut self, node: Id, train: Id) {
+        if let Some(queue) = self.queues.get_mut(&node) {
+            queue.retain(|&id| id != train);
+            if queue.is_empty() {
+                self.queues.remove(&node);
+            }
+        }
+    }
+
+    /// Place of `train` in the queue for `node`, 0 meaning it has the platform
+    pub fn position(&self, node: Id, train: Id) -> Option<usize> {
+        self.queues.get(&node)?.iter().position(|&id| id == train)
+    }
+}
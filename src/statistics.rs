@@ -0,0 +1,212 @@
+use super::*;
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Deserialize)]
+pub struct StatisticsConfig {
+    /// Seconds covered by one sample
+    pub window: f32,
+    /// Samples kept for the graphs and the export
+    pub history: usize,
+    /// In `target_window_height` units
+    pub graph_width: f32,
+    pub graph_height: f32,
+    pub line_width: f32,
+    pub background_color: Rgba<f32>,
+    pub utilisation_color: Rgba<f32>,
+    pub trip_color: Rgba<f32>,
+    /// Where the CSV export is written
+    pub export_path: PathBuf,
+}
+
+/// Totals over one time window
+#[derive(Default)]
+pub struct Sample {
+    /// Seconds since the start of the game when the window began
+    pub start: f32,
+    pub produced: HashMap<Id, f32>,
+    pub consumed: HashMap<Id, f32>,
+    /// Unloaded into factory inputs
+    pub delivered: HashMap<Id, f32>,
+    pub loaded_distance: f32,
+    pub empty_distance: f32,
+    /// Trains that reached their target
+    pub trips: usize,
+    /// Total time those trains spent on the way
+    pub trip_time: f32,
+}
+
+impl Sample {
+    /// Share of the distance driven with cargo, between 0 and 1
+    pub fn utilisation(&self) -> f32 {
+        let total = self.loaded_distance + self.empty_distance;
+        if total > 0.0 {
+            self.loaded_distance / total
+        } else {
+            0.0
+        }
+    }
+
+    pub fn average_trip(&self) -> f32 {
+        if self.trips > 0 {
+            self.trip_time / self.trips as f32
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Network activity sampled over fixed time windows
+#[derive(Default)]
+pub struct Statistics {
+    pub current: Sample,
+    /// Finished windows, oldest first
+    pub samples: VecDeque<Sample>,
+}
+
+impl Statistics {
+    pub fn produce(&mut self, resource: Id, amount: f32) {
+        *self.current.produced.entry(resource).or_default() += amount;
+    }
+
+    pub fn consume(&mut self, resource: Id, amount: f32) {
+        *self.current.consumed.entry(resource).or_default() += amount;
+    }
+
+    pub fn deliver(&mut self, resource: Id, amount: f32) {
+        *self.current.delivered.entry(resource).or_default() += amount;
+    }
+
+    pub fn drive(&mut self, distance: f32, loaded: bool) {
+        if loaded {
+            self.current.loaded_distance += distance;
+        } else {
+            self.current.empty_distance += distance;
+        }
+    }
+
+    pub fn trip(&mut self, time: f32) {
+        self.current.trips += 1;
+        self.current.trip_time += time;
+    }
+
+    /// Close the current window once it gets `config.window` long
+    pub fn update(&mut self, time: f32, config: &StatisticsConfig) {
+        if time - self.current.start < config.window {
+            return;
+        }
+        let next = Sample {
+            start: time,
+            ..default()
+        };
+        self.samples
+            .push_back(mem::replace(&mut self.current, next));
+        while self.samples.len() > config.history {
+            self.samples.pop_front();
+        }
+    }
+
+    /// One row per finished window, amounts being totals over the window
+    pub fn to_csv(&self, resources: &[&Resource]) -> String {
+        let mut header = vec!["start".to_owned()];
+        for resource in resources {
+            for column in ["produced", "consumed", "delivered"] {
+                header.push(format!("{} {column}", resource.name));
+            }
+        }
+        header.extend(
+            ["loaded distance", "empty distance", "trips", "average trip"].map(String::from),
+        );
+        let mut csv = csv_row(&header);
+        for sample in &self.samples {
+            let mut row = vec![format!("{:.2}", sample.start)];
+            for resource in resources {
+                for amounts in [&sample.produced, &sample.consumed, &sample.delivered] {
+                    let amount = amounts.get(&resource.id).copied().unwrap_or(0.0);
+                    row.push(format!("{amount:.2}"));
+                }
+            }
+            row.push(format!("{:.2}", sample.loaded_distance));
+            row.push(format!("{:.2}", sample.empty_distance));
+            row.push(sample.trips.to_string());
+            row.push(format!("{:.2}", sample.average_trip()));
+            csv += &csv_row(&row);
+        }
+        csv
+    }
+
+    /// Graphs of the finished windows, amounts per minute
+    pub fn graphs(&self, resources: &[&Resource], config: &StatisticsConfig) -> Vec<Graph> {
+        let per_minute = 60.0 / config.window;
+        let resource_graph = |title: &str, amounts: fn(&Sample) -> &HashMap<Id, f32>| Graph {
+            title: title.to_owned(),
+            series: resources
+                .iter()
+                .map(|resource| Series {
+                    color: resource.color,
+                    values: self
+                        .samples
+                        .iter()
+                        .map(|sample| {
+                            amounts(sample).get(&resource.id).copied().unwrap_or(0.0) * per_minute
+                        })
+                        .collect(),
+                })
+                .collect(),
+        };
+        let graph = |title: &str, color: Rgba<f32>, value: fn(&Sample) -> f32| Graph {
+            title: title.to_owned(),
+            series: vec![Series {
+                color,
+                values: self.samples.iter().map(value).collect(),
+            }],
+        };
+        vec![
+            resource_graph("Produced per minute", |sample| &sample.produced),
+            resource_graph("Consumed per minute", |sample| &sample.consumed),
+            resource_graph("Delivered per minute", |sample| &sample.delivered),
+            graph("Loaded distance %", config.utilisation_color, |sample| {
+                sample.utilisation() * 100.0
+            }),
+            graph("Average trip, s", config.trip_color, Sample::average_trip),
+        ]
+    }
+}
+
+pub struct Series {
+    pub color: Rgba<f32>,
+    pub values: Vec<f32>,
+}
+
+pub struct Graph {
+    pub title: String,
+    pub series: Vec<Series>,
+}
+
+impl Graph {
+    /// Largest value of all series, at least 1 so that empty graphs stay flat
+    pub fn max(&self) -> f32 {
+        self.series
+            .iter()
+            .flat_map(|series| &series.values)
+            .copied()
+            .fold(1.0, f32::max)
+    }
+}
+
+/// Fields joined with commas, quoted where they hold a comma, quote or line break, as
+/// resource names come from the config and could hold anything
+fn csv_row(fields: &[String]) -> String {
+    let row = fields
+        .iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.clone()
+            }
+        })
+        .join(",");
+    row + "\n"
+}
@@ -0,0 +1,36 @@
+//! Export of hand made samples
+
+use super::*;
+
+#[test]
+fn csv() {
+    let mut id_gen = IdGen::new();
+    let resource = Resource {
+        id: id_gen.gen(),
+        name: "Plate, \"pig\"".to_owned(),
+        color: Rgba::WHITE,
+    };
+    let mut statistics = Statistics::default();
+    statistics.produce(resource.id, 3.0);
+    statistics.trip(10.0);
+    statistics.trip(20.0);
+    let next = Sample {
+        start: 60.0,
+        ..default()
+    };
+    statistics
+        .samples
+        .push_back(mem::replace(&mut statistics.current, next));
+
+    let csv = statistics.to_csv(&[&resource]);
+    let lines: Vec<&str> = csv.lines().collect();
+    // Names with commas or quotes stay in their column
+    assert_eq!(
+        lines,
+        [
+            "start,\"Plate, \"\"pig\"\" produced\",\"Plate, \"\"pig\"\" consumed\",\
+             \"Plate, \"\"pig\"\" delivered\",loaded distance,empty distance,trips,average trip",
+            "0.00,3.00,0.00,0.00,0.00,0.00,2,15.00",
+        ]
+    );
+}
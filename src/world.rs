@@ -0,0 +1,776 @@
+use super::*;
+
+/// End of a track to build, either an existing node or a new one at a position
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub(crate) enum TrackEnd {
+    Node(Id),
+    New(vec2<f32>),
+}
+
+/// Where to put a station
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub(crate) enum StationSite {
+    Node(Id),
+    /// Splits the track at this point
+    Track(TrackPoint),
+}
+
+/// Everything that gets simulated, without any of the window, camera or input state, so it
+/// can run headless
+pub(crate) struct World {
+    pub id_gen: IdGen,
+    pub config: Rc<Config>,
+    pub factory_types: Rc<FactoryTypes>,
+    /// Of the map and the rng
+    pub seed: u64,
+    /// Seeded from the map seed, so the same commands lead to the same game
+    pub rng: StdRng,
+    pub map: Map,
+    pub tracks: Tracks,
+    pub trains: Collection<Train>,
+    pub resources: Collection<Resource>,
+    pub factories: Collection<Factory>,
+    pub stations: Collection<Station>,
+    /// Counts up with every generated station name, so that no name comes back after its
+    /// station is removed
+    pub named_stations: u32,
+    pub queues: StationQueues,
+    /// Keyed by the station or factory io node
+    pub station_stats: HashMap<Id, StationStats>,
+    pub statistics: Statistics,
+    pub traffic: Traffic,
+    pub time: f32,
+    /// Simulation steps taken
+    pub tick: u64,
+    pub wallet: Wallet,
+    pub scenario_name: Option<String>,
+    pub goals: Vec<Goal>,
+    /// Seconds since the start to complete the goals in
+    pub time_limit: Option<f32>,
+    /// Set once the scenario is over, which stops the simulation
+    pub outcome: Option<Outcome>,
+}
+
+impl World {
+    /// Read the config and factory types from `assets` and start a world, from a scenario
+    /// file if given
+    pub async fn load(assets: &Path, seed: Option<u64>, scenario: Option<&Path>) -> Self {
+        let config: Config = file::load_detect(assets.join("config.toml")).await.unwrap();
+        let factory_types: FactoryTypes = file::load_detect(assets.join("factories.toml"))
+            .await
+            .unwrap();
+        let scenario: Option<Scenario> = match scenario {
+            Some(path) => Some(file::load_detect(path).await.unwrap()),
+            None => None,
+        };
+        Self::new(
+            Rc::new(config),
+            Rc::new(factory_types),
+            seed,
+            scenario.as_ref(),
+        )
+    }
+
+    /// Seed precedence is `seed`, then the scenario, then the config, then a random one
+    pub fn new(
+        config: Rc<Config>,
+        factory_types: Rc<FactoryTypes>,
+        seed: Option<u64>,
+        scenario: Option<&Scenario>,
+    ) -> Self {
+        let seed = seed
+            .or(scenario.and_then(|scenario| scenario.seed))
+            .or(config.map.seed)
+            .unwrap_or_else(|| thread_rng().gen());
+        let mut world = Self {
+            id_gen: IdGen::new(),
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            map: Map::generate(&config.map, seed),
+            tracks: Tracks::new(config.track.index_cell_size),
+            trains: Collection::new(),
+            resources: default(),
+            factories: default(),
+            stations: default(),
+            named_stations: 0,
+            queues: default(),
+            station_stats: default(),
+            statistics: default(),
+            traffic: default(),
+            time: 0.0,
+            tick: 0,
+            wallet: Wallet::new(config.economy.starting_balance),
+            scenario_name: None,
+            goals: Vec::new(),
+            time_limit: None,
+            outcome: None,
+            config,
+            factory_types,
+        };
+        if let Some(scenario) = scenario {
+            world.load_scenario(scenario);
+        }
+        world
+    }
+
+    pub fn add_node(&mut self, pos: vec2<f32>) -> Id {
+        self.tracks.add_node(TrackNode::new(&mut self.id_gen, pos))
+    }
+
+    /// A free node placed by the player, only on ground that can be built on
+    pub fn place_node(&mut self, pos: vec2<f32>) -> Result<Id, String> {
+        let terrain = self.map.terrain_at(pos);
+        if !terrain.is_buildable() {
+            return Err(format!("Can't build on {}", terrain.name()));
+        }
+        Ok(self.add_node(pos))
+    }
+
+    fn track_end_pos(&self, end: TrackEnd) -> vec2<f32> {
+        match end {
+            TrackEnd::Node(id) => self.tracks.nodes.get(&id).unwrap().pos,
+            TrackEnd::New(pos) => pos,
+        }
+    }
+
+    /// Pay for and lay a track between two ends, returning the node it ends at
+    pub fn build_track(
+        &mut self,
+        start: TrackEnd,
+        end: TrackEnd,
+        handles: [vec2<f32>; 2],
+    ) -> Result<Id, String> {
+        if let (TrackEnd::Node(a), TrackEnd::Node(b)) = (start, end) {
+            if a == b {
+                return Err("Track can't end where it starts".to_owned());
+            }
+            if self.tracks.nodes.get(&a).unwrap().connections.contains(&b) {
+                return Err("Already connected".to_owned());
+            }
+        }
+        let plan = self.plan_track(self.track_end_pos(start), self.track_end_pos(end), handles)?;
+        self.pay(plan.price)?;
+        let [start, end] = [start, end].map(|end| match end {
+            TrackEnd::Node(id) => id,
+            TrackEnd::New(pos) => self.add_node(pos),
+        });
+        self.tracks.add_curve(start, end, handles);
+        self.tracks.set_kind(start, end, plan.kind);
+        Ok(end)
+    }
+
+    pub fn plan_track(
+        &self,
+        start: vec2<f32>,
+        end: vec2<f32>,
+        handles: [vec2<f32>; 2],
+    ) -> Result<TrackPlan, String> {
+        plan_track(
+            &self.map,
+            &self.config.track,
+            Curve::new(start, end, handles).points(),
+        )
+    }
+
+    /// Spend `price`, or explain that there isn't enough money
+    pub(crate) fn pay(&mut self, price: f32) -> Result<(), String> {
+        if self.wallet.spend(price) {
+            return Ok(());
+        }
+        Err(format!("Not enough money, ${price:.0} needed"))
+    }
+
+    pub fn spawn_factory(
+        &mut self,
+        pos: vec2<f32>,
+        angle: Angle<f32>,
+        factory_type_index: usize,
+    ) -> Result<(), String> {
+        let Some(factory_type) = self.factory_types.get(factory_type_index) else {
+            return Ok(());
+        };
+        let price = factory_type.price;
+        self.check_factory_site(pos, factory_type_index)?;
+        self.pay(price)?;
+        self.build_factory(pos, angle, factory_type_index);
+        Ok(())
+    }
+
+    pub fn deposit_type(&self, resource: &str) -> Option<usize> {
+        self.config
+            .map
+            .deposit
+            .iter()
+            .position(|deposit| deposit.resource == resource)
+    }
+
+    /// Id of the resource called `name`, registering it if it's new
+    pub fn resource_id(&mut self, name: &str) -> Id {
+        if let Some(existing) = self.resources.iter().find(|resource| resource.name == name) {
+            return existing.id;
+        }
+        let id = self.id_gen.gen();
+        let color = self
+            .factory_types
+            .resource
+            .iter()
+            .find(|resource| resource.name == name)
+            .map_or(self.factory_types.default_resource_color, |resource| {
+                resource.color
+            });
+        self.resources.insert(Resource {
+            id,
+            name: name.to_owned(),
+            color,
+        });
+        id
+    }
+
+    /// Where the ios of a factory turned by `angle` go, spread evenly around its edge
+    pub fn io_positions(
+        &self,
+        pos: vec2<f32>,
+        angle: Angle<f32>,
+        factory_type_index: usize,
+    ) -> Vec<vec2<f32>> {
+        let factory_type = &self.factory_types[factory_type_index];
+        let count = factory_type.io.len();
+        (0..count)
+            .map(|index| {
+                let turn = Angle::from_degrees(360.0 * index as f32 / count as f32);
+                pos + vec2(factory_type.radius, 0.0).rotate(angle + turn)
+            })
+            .collect()
+    }
+
+    pub fn build_factory(
+        &mut self,
+        pos: vec2<f32>,
+        angle: Angle<f32>,
+        factory_type_index: usize,
+    ) -> Id {
+        let factory_types = self.factory_types.clone();
+        let factory_type = &factory_types[factory_type_index];
+        let resources: Vec<Id> = factory_type
+            .io
+            .iter()
+            .map(|io| self.resource_id(&io.resource))
+            .collect();
+        let deposit = factory_type
+            .deposit
+            .as_ref()
+            .and_then(|resource| self.deposit_type(resource));
+        let id = self.id_gen.gen();
+        let factory = Factory {
+            ty: factory_type_index,
+            id,
+            pos,
+            deposit,
+            io: factory_type
+                .io
+                .iter()
+                .zip(self.io_positions(pos, angle, factory_type_index))
+                .enumerate()
+                .map(|(index, (io, io_pos))| FactoryIo {
+                    ty: io.r#type,
+                    node: self.add_node(io_pos),
+                    amount: io.speed.is_some().then_some(0.0),
+                    resource: resources[index],
+                    pos: io_pos,
+                    loader: io.loader,
+                    sale_price: io.sale_price,
+                    delivered: 0.0,
+                })
+                .collect(),
+        };
+        self.factories.insert(factory);
+        id
+    }
+
+    /// Buy a train carrying a random resource, standing at a random node
+    pub fn spawn_train(&mut self, wagon: usize, schedule: Vec<String>) -> Result<Id, String> {
+        // Sorted so that the pick only depends on the rng
+        let resources: Vec<Id> = self
+            .resources
+            .iter()
+            .map(|resource| resource.id)
+            .sorted()
+            .collect();
+        let Some(&resource) = resources.choose(&mut self.rng) else {
+            return Err("Nothing to carry yet".to_owned());
+        };
+        let nodes: Vec<Id> = self
+            .tracks
+            .nodes
+            .iter()
+            .map(|node| node.id)
+            .sorted()
+            .collect();
+        let Some(&node) = nodes.choose(&mut self.rng) else {
+            return Err("No tracks to put a train on".to_owned());
+        };
+        self.pay(self.config.economy.train_price)?;
+        Ok(self.build_train(resource, node, wagon, schedule))
+    }
+
+    pub fn build_train(
+        &mut self,
+        resource: Id,
+        node: Id,
+        wagon: usize,
+        schedule: Vec<String>,
+    ) -> Id {
+        let id = self.id_gen.gen();
+        let train = Train {
+            target: None,
+            id,
+            length: self.config.test.train_length,
+            resource,
+            amount: 0.0,
+            head: TrackPoint {
+                from: node,
+                to: node,
+                ratio: 0.0,
+            },
+            tail_nodes: default(),
+            path_from_target: None,
+            schedule,
+            next_stop: 0,
+            wagon,
+            dwell: 0.0,
+            last_dwell: None,
+            trip: 0.0,
+        };
+        self.trains.insert(train);
+        id
+    }
+
+    /// Split the track at `point`, keeping trains on the split segment where they are
+    pub fn split_segment(&mut self, point: TrackPoint) -> Id {
+        let (a, b) = (point.from, point.to);
+        let length = self.tracks.segment_length(a, b);
+        let node = self.tracks.split_segment(point, &mut self.id_gen);
+        self.traffic.split(a, b, node);
+        let split_distance = point.ratio * length;
+        for train in &mut self.trains {
+            let head = train.head;
+            if Tracks::segment_key(head.from, head.to) == Tracks::segment_key(a, b) {
+                let travelled = head.ratio * length;
+                let split_at = if head.from == a {
+                    split_distance
+                } else {
+                    length - split_distance
+                };
+                train.head = if travelled < split_at {
+                    // The old end is still to come, after the new node
+                    if let Some(path) = &mut train.path_from_target {
+                        if path.last() != Some(&head.to) {
+                            path.push(head.to);
+                        }
+                    }
+                    TrackPoint {
+                        from: head.from,
+                        to: node,
+                        ratio: travelled / self.tracks.segment_length(head.from, node),
+                    }
+                } else {
+                    train.tail_nodes.push_front(node);
+                    TrackPoint {
+                        from: node,
+                        to: head.to,
+                        ratio: (travelled - split_at) / self.tracks.segment_length(node, head.to),
+                    }
+                };
+            }
+            let mut tail_nodes = Vec::from(mem::take(&mut train.tail_nodes));
+            insert_between(&mut tail_nodes, a, b, node);
+            train.tail_nodes = tail_nodes.into();
+            if let Some(path) = &mut train.path_from_target {
+                insert_between(path, a, b, node);
+            }
+        }
+        node
+    }
+
+    /// Pay for and build a station, returning its node
+    pub fn place_station(&mut self, site: StationSite) -> Result<Id, String> {
+        match site {
+            StationSite::Node(id) => {
+                if self.stations.iter().any(|station| station.node == id) {
+                    return Err("There is a station here already".to_owned());
+                }
+            }
+            StationSite::Track(point) => {
+                let terrain = self.map.terrain_at(self.tracks.point_pos(point));
+                if !terrain.is_buildable() {
+                    return Err(format!("Can't build on {}", terrain.name()));
+                }
+            }
+        }
+        self.pay(self.config.economy.station_price)?;
+        let node = match site {
+            StationSite::Node(id) => id,
+            StationSite::Track(point) => self.split_segment(point),
+        };
+        let name = self.next_station_name();
+        self.build_station(node, name);
+        Ok(node)
+    }
+
+    /// Name for a new station, never given out before and not taken by a scenario station
+    pub fn next_station_name(&mut self) -> String {
+        loop {
+            self.named_stations += 1;
+            let name = format!("Station {}", self.named_stations);
+            if !self.stations.iter().any(|station| station.name == name) {
+                return name;
+            }
+        }
+    }
+
+    pub fn build_station(&mut self, node: Id, name: String) {
+        let station = Station {
+            id: self.id_gen.gen(),
+            name,
+            node,
+            platform_length: self.config.station.platform_length,
+            loader: 0,
+        };
+        self.stations.insert(station);
+    }
+
+    /// Move the loader of the station or factory io at `node` one tier up
+    pub fn upgrade_loader(&mut self, node: Id) -> Result<(), String> {
+        let station = self.stations.iter().find(|station| station.node == node);
+        let io = self
+            .factories
+            .iter()
+            .flat_map(|factory| &factory.io)
+            .find(|io| io.node == node);
+        let Some(loader) = station
+            .map(|station| station.loader)
+            .or(io.map(|io| io.loader))
+        else {
+            return Ok(());
+        };
+        let Some(next) = self.factory_types.loader.get(loader + 1) else {
+            return Ok(());
+        };
+        self.pay(next.price)?;
+        if let Some(station) = self
+            .stations
+            .iter_mut()
+            .find(|station| station.node == node)
+        {
+            station.loader = loader + 1;
+        }
+        for io in self
+            .factories
+            .iter_mut()
+            .flat_map(|factory| &mut factory.io)
+        {
+            if io.node == node {
+                io.loader = loader + 1;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn target_name(&self, target: Target) -> String {
+        match target {
+            Target::Io { io, .. } => {
+                let factory = self.factories.get(&io.factory).unwrap();
+                let resource = self.resources.get(&factory.io[io.io].resource).unwrap();
+                format!(
+                    "{} ({})",
+                    self.factory_types[factory.ty].name, resource.name
+                )
+            }
+            Target::Station { id, .. } => self.stations.get(&id).unwrap().name.clone(),
+        }
+    }
+
+    pub fn update(&mut self, delta_time: f32) {
+        self.tick += 1;
+        if self.outcome.is_some() {
+            return;
+        }
+        self.time += delta_time;
+        self.wallet
+            .charge(self.trains.len() as f32 * self.config.economy.train_running_cost * delta_time);
+
+        // Factories can share a deposit, so keep the order they extract in stable
+        let factory_ids: Vec<Id> = self
+            .factories
+            .iter()
+            .map(|factory| factory.id)
+            .sorted()
+            .collect();
+        for id in &factory_ids {
+            let factory = self.factories.get_mut(id).unwrap();
+            let factory_type = &self.factory_types[factory.ty];
+            let radius = factory_type.radius;
+            let mut max_input_dt = delta_time;
+            for (io, io_config) in factory.io.iter().zip(&factory_type.io) {
+                if io.ty == IoType::Input {
+                    if let (Some(amount), Some(speed)) = (io.amount, io_config.speed) {
+                        max_input_dt = max_input_dt.min(amount / speed);
+                    }
+                }
+            }
+            for (io, io_config) in factory.io.iter_mut().zip(&factory_type.io) {
+                match io.ty {
+                    IoType::Input => {
+                        if let (Some(amount), Some(speed)) = (&mut io.amount, io_config.speed) {
+                            let before = *amount;
+                            *amount = (*amount - speed * max_input_dt).max(0.0);
+                            self.statistics.consume(io.resource, before - *amount);
+                        }
+                    }
+                    IoType::Output => {
+                        if let (Some(amount), Some(speed)) = (&mut io.amount, io_config.speed) {
+                            let produced = match factory.deposit {
+                                Some(ty) => {
+                                    self.map
+                                        .extract(ty, factory.pos, radius, speed * max_input_dt)
+                                }
+                                None => speed * max_input_dt,
+                            };
+                            *amount += produced;
+                            self.statistics.produce(io.resource, produced);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut congestion = Congestion::new(&self.trains);
+        // Trains join station queues in this order, so keep it stable
+        let train_ids: Vec<Id> = self.trains.iter().map(|train| train.id).sorted().collect();
+        for &id in &train_ids {
+            let train = self.trains.get_mut(&id).unwrap();
+            if train.path_from_target.is_some() {
+                continue;
+            }
+            if let Some(target) = train.target {
+                if self.queues.position(target.node(), train.id) != Some(0) {
+                    continue;
+                }
+            }
+            let go = match train.target {
+                Some(target) => {
+                    let (ios, loader) = match target {
+                        Target::Io { io, .. } => (
+                            vec![io],
+                            self.factories.get(&io.factory).unwrap().io[io.io].loader,
+                        ),
+                        Target::Station { id, .. } => {
+                            let station = self.stations.get(&id).unwrap();
+                            let ios = station.nearby_ios(
+                                &self.tracks,
+                                &self.factories,
+                                &self.factory_types,
+                                &self.config.station,
+                                train.resource,
+                            );
+                            (ios, station.loader)
+                        }
+                    };
+                    let speed = self.factory_types.loader[loader].speed
+                        * self.config.train.wagon[train.wagon].load_speed;
+                    train.dwell += delta_time;
+                    let stats = self.station_stats.entry(target.node()).or_default();
+                    let unloaded = stats.unloaded;
+                    let done = transfer_cargo(
+                        train,
+                        &mut self.factories,
+                        &ios,
+                        self.config.train.capacity,
+                        speed * delta_time,
+                        stats,
+                        &mut self.wallet,
+                    );
+                    self.statistics
+                        .deliver(train.resource, stats.unloaded - unloaded);
+                    done
+                }
+                None => true,
+            };
+
+            if go {
+                congestion.record(train, -1);
+                if let Some(target) = train.target.take() {
+                    self.queues.leave(target.node(), train.id);
+                    let stats = self.station_stats.entry(target.node()).or_default();
+                    stats.visits += 1;
+                    stats.dwell += train.dwell;
+                    train.last_dwell = Some(mem::take(&mut train.dwell));
+                }
+                let routing = &self.config.track.routing;
+                let came_from = (train.head.from != train.head.to).then_some(train.head.from);
+                let route = |node: Id| {
+                    self.tracks.route(
+                        train.head.to,
+                        came_from,
+                        node,
+                        &congestion,
+                        self.config.test.train_speed,
+                        &self.config.track,
+                    )
+                };
+                // Ties go to the lowest node, so the choice doesn't depend on iteration order
+                let target = if let Some(name) = train.schedule.get(train.next_stop) {
+                    self.stations
+                        .iter()
+                        .filter(|station| &station.name == name)
+                        .filter_map(|station| {
+                            let (path, cost) = route(station.node)?;
+                            let target = Target::Station {
+                                id: station.id,
+                                node: station.node,
+                            };
+                            Some((target, path, cost))
+                        })
+                        .min_by_key(|&(target, _, cost)| (r32(cost), target.node()))
+                } else {
+                    let look_for = if train.amount > self.config.train.capacity / 2.0 {
+                        IoType::Input
+                    } else {
+                        IoType::Output
+                    };
+                    self.factories
+                        .iter()
+                        .flat_map(|factory| {
+                            factory
+                                .io
+                                .iter()
+                                .enumerate()
+                                .map(|(index, io)| (factory.id, index, io))
+                        })
+                        .filter(|(_, _, io)| io.ty == look_for && io.resource == train.resource)
+                        .filter_map(|(factory_id, io_index, io)| {
+                            let (path, cost) = route(io.node)?;
+                            let cost = cost
+                                + routing.waiting_train_penalty
+                                    * congestion.waiting(io.node) as f32;
+                            let target = Target::Io {
+                                io: IoId {
+                                    factory: factory_id,
+                                    io: io_index,
+                                },
+                                node: io.node,
+                            };
+                            Some((target, path, cost))
+                        })
+                        .min_by_key(|&(target, _, cost)| (r32(cost), target.node()))
+                };
+                if let Some((target, mut path, _cost)) = target {
+                    path.reverse();
+                    train.path_from_target = Some(path);
+                    train.target = Some(target);
+                    self.queues.join(target.node(), train.id);
+                    if !train.schedule.is_empty() {
+                        train.next_stop = (train.next_stop + 1) % train.schedule.len();
+                    }
+                }
+                congestion.record(train, 1);
+            }
+        }
+
+        for train in &mut self.trains {
+            let Some(path) = &mut train.path_from_target else {
+                continue;
+            };
+            while path.last() == Some(&train.head.to) {
+                path.pop();
+            }
+            let from = self.tracks.nodes.get(&train.head.from).unwrap();
+            let to = self.tracks.nodes.get(&train.head.to).unwrap();
+            let current_segment_length = self.tracks.segment_length(from.id, to.id);
+            let mut current_segment_progress = train.head.ratio * current_segment_length;
+            let start_progress = current_segment_progress;
+            train.trip += delta_time;
+            let speed = self.tracks.speed(
+                from.id,
+                to.id,
+                self.config.test.train_speed,
+                &self.config.track,
+            );
+            let full_advance = speed * delta_time;
+            let mut advance = full_advance;
+            let queue_position = train
+                .target
+                .and_then(|target| self.queues.position(target.node(), train.id));
+            if let Some(place) = queue_position.filter(|&place| place > 0) {
+                // Hold back far enough to leave room for the trains ahead in the queue
+                let remaining = current_segment_length - current_segment_progress
+                    + std::iter::once(to.id)
+                        .chain(path.iter().rev().copied())
+                        .tuple_windows()
+                        .map(|(a, b)| self.tracks.segment_length(a, b))
+                        .sum::<f32>();
+                let hold = place as f32 * (train.length + self.config.station.queue_spacing);
+                advance = advance.min(remaining - hold).max(0.0);
+                if train.head.from != train.head.to {
+                    let held = delta_time * (1.0 - advance / full_advance);
+                    self.traffic.wait(train.head.from, train.head.to, held);
+                }
+            }
+            current_segment_progress += advance;
+            let mut moved = advance;
+            if current_segment_progress < current_segment_length {
+                train.head.ratio = current_segment_progress / current_segment_length;
+            } else if let Some(next_node) = path.pop() {
+                let next_node = self.tracks.nodes.get(&next_node).unwrap();
+                let next_segment_length = self.tracks.segment_length(to.id, next_node.id);
+                // What is left of this step goes at the next segment's speed
+                let next_speed = self.tracks.speed(
+                    to.id,
+                    next_node.id,
+                    self.config.test.train_speed,
+                    &self.config.track,
+                );
+                let next_segment_progress =
+                    (current_segment_progress - current_segment_length) * next_speed / speed;
+                train.head = TrackPoint {
+                    from: to.id,
+                    to: next_node.id,
+                    ratio: next_segment_progress / next_segment_length,
+                };
+                train.tail_nodes.push_front(to.id);
+                self.traffic
+                    .enter(to.id, next_node.id, train.resource, train.amount);
+
+                let mut covered_length = next_segment_progress;
+                for (i, (a, b)) in train.tail_nodes.iter().copied().tuple_windows().enumerate() {
+                    if covered_length > train.length {
+                        train.tail_nodes.truncate(i + 1);
+                        break;
+                    }
+                    covered_length += self.tracks.segment_length(a, b);
+                }
+            } else {
+                moved = (current_segment_length - start_progress).max(0.0);
+                train.head.ratio = 1.0;
+                train.path_from_target = None;
+                self.statistics.trip(mem::take(&mut train.trip));
+            }
+            self.statistics.drive(moved, train.amount > 0.0);
+        }
+        self.statistics.update(self.time, &self.config.statistics);
+
+        self.outcome = self.check_outcome();
+    }
+}
+
+/// Put `node` between every consecutive `a`, `b` pair of `nodes`, in either order
+fn insert_between(nodes: &mut Vec<Id>, a: Id, b: Id, node: Id) {
+    let mut i = 1;
+    while i < nodes.len() {
+        if (nodes[i - 1], nodes[i]) == (a, b) || (nodes[i - 1], nodes[i]) == (b, a) {
+            nodes.insert(i, node);
+            i += 1;
+        }
+        i += 1;
+    }
+}